@@ -0,0 +1,325 @@
+//! Compressed archives of removed files
+//!
+//! Rather than deleting old DXF files outright, they can be packed into
+//! a compressed archive (zip or tar.zst) for each job, which takes a
+//! fraction of the space but still lets an old job be re-nested.
+//!
+//! Each archive holds a [`Manifest`] (as `manifest.json`, after the
+//! files) with the size, modified time and SHA-256 hash of every file
+//! in it. Files are stored under their path relative to the root they
+//! came from (i.e. `1210123/Fab/Plates/DXF/part.dxf`), so they can be
+//! put back where they were.
+//!
+//! An archive is always read back and checked against its manifest
+//! ([`verify`]) before the files in it are removed.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use wax::{Glob, Pattern};
+
+use crate::audit;
+use crate::cleanup::FileInfo;
+
+/// Name of the manifest in an archive
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Name of the archive folder in a job folder, when archives are kept with their job
+pub const ARCHIVE_DIR: &str = "_archive";
+
+/// Compression level used for tar.zst archives
+const ZSTD_LEVEL: i32 = 19;
+
+/// Kind of archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// zip archive, using deflate
+    Zip,
+
+    /// tar archive, compressed with zstd
+    TarZst,
+}
+
+impl Format {
+    /// File extension for the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::TarZst => "tar.zst",
+        }
+    }
+
+    /// Format of an archive, from its file name
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+
+        [Self::Zip, Self::TarZst].into_iter()
+            .find(|format| name.ends_with(&format!(".{}", format.extension())))
+    }
+}
+
+/// Contents of an archive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// when the archive was made
+    pub created: DateTime<Local>,
+
+    /// run that made the archive
+    pub run: String,
+
+    /// root the files came from
+    pub root: PathBuf,
+
+    /// files in the archive
+    pub files: Vec<ArchivedFile>,
+}
+
+/// A file in an archive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedFile {
+    /// path of the file relative to the root, with `/` separators
+    pub path: String,
+
+    /// size of the file, in bytes
+    pub size: u64,
+
+    /// last modified time of the file
+    pub modified: DateTime<Local>,
+
+    /// SHA-256 hash of the file contents
+    pub sha256: String,
+}
+
+/// Pack files into an archive, returning its manifest
+///
+/// The archive is written next to `path` and only renamed into place
+/// once it is complete.
+pub fn create(path: &Path, run: &str, root: &Path, files: &[&FileInfo]) -> io::Result<Manifest> {
+    let format = Format::from_path(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "archive must be a `.zip` or `.tar.zst` file"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".partial");
+    let temp = PathBuf::from(temp);
+
+    let write = || {
+        let mut manifest = Manifest { created: Local::now(), run: run.to_string(), root: root.to_path_buf(), files: Vec::new() };
+        let mut writer = Writer::new(format, File::create(&temp)?)?;
+        for file in files {
+            let name = relative(root, &file.path)?;
+            let bytes = fs::read(&file.path)?;
+
+            writer.add(&name, &bytes, file.modified)?;
+            manifest.files.push(ArchivedFile {
+                path: name,
+                size: bytes.len() as u64,
+                modified: file.modified,
+                sha256: audit::sha256_bytes(&bytes),
+            });
+        }
+
+        writer.add(MANIFEST_FILE, &serde_json::to_vec_pretty(&manifest)?, manifest.created)?;
+        writer.finish()?.sync_all()?;
+        fs::rename(&temp, path)?;
+
+        Ok(manifest)
+    };
+
+    // a partly written archive is of no use
+    write().inspect_err(|_| { let _ = fs::remove_file(&temp); })
+}
+
+/// Read an archive back and check that every file in `expected` is in
+/// it, with the same contents
+pub fn verify(path: &Path, expected: &Manifest) -> io::Result<()> {
+    let stored = read_manifest(path)?;
+    if stored != *expected {
+        return Err(invalid(format!("manifest in `{}` does not match", path.display())));
+    }
+
+    let mut seen = 0;
+    read_entries(path, |name, reader| {
+        if name == MANIFEST_FILE {
+            return Ok(());
+        }
+
+        let file = expected.files.iter().find(|f| f.path == name)
+            .ok_or_else(|| invalid(format!("`{}` is not in the manifest", name)))?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != file.size || audit::sha256_bytes(&bytes) != file.sha256 {
+            return Err(invalid(format!("`{}` does not match the manifest", name)));
+        }
+
+        seen += 1;
+        Ok(())
+    })?;
+
+    match seen == expected.files.len() {
+        true => Ok(()),
+        false => Err(invalid(format!("`{}` holds {} of the {} files in its manifest", path.display(), seen, expected.files.len()))),
+    }
+}
+
+/// Read the manifest of an archive
+pub fn read_manifest(path: &Path) -> io::Result<Manifest> {
+    let mut manifest = None;
+    read_entries(path, |name, reader| {
+        if name == MANIFEST_FILE {
+            manifest = Some(serde_json::from_reader(reader)?);
+        }
+
+        Ok(())
+    })?;
+
+    manifest.ok_or_else(|| invalid(format!("`{}` has no manifest", path.display())))
+}
+
+/// Take files out of an archive into `dest` (under their path relative
+/// to the root), returning the files extracted
+///
+/// Only files matching `glob` (relative to the root) are extracted, if
+/// it is given. Files that already exist are never overwritten, and each
+/// file is checked against the manifest before it is put in place.
+///
+/// Extracted files are given a new modified time, so that the next run
+/// does not remove them again right away. The files returned keep the
+/// modified time from the manifest.
+pub fn extract(path: &Path, dest: &Path, glob: Option<&Glob>) -> io::Result<Vec<(FileInfo, String)>> {
+    let manifest = read_manifest(path)?;
+
+    let mut extracted = Vec::new();
+    read_entries(path, |name, reader| {
+        let Some(file) = manifest.files.iter().find(|f| f.path == name) else { return Ok(()) };
+        if glob.is_some_and(|glob| !glob.is_match(name)) {
+            return Ok(());
+        }
+
+        // never write outside of `dest`
+        if !Path::new(name).components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(invalid(format!("`{}` in `{}` is not a relative path", name, path.display())));
+        }
+
+        let target = dest.join(name);
+        if target.exists() {
+            log::warn!("Not extracting `{}` (already exists)", target.display());
+            return Ok(());
+        }
+
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if audit::sha256_bytes(&bytes) != file.sha256 {
+            return Err(invalid(format!("`{}` in `{}` does not match the manifest", name, path.display())));
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let out = File::create(&target)?;
+        (&out).write_all(&bytes)?;
+        out.set_modified(SystemTime::now())?;
+
+        let info = FileInfo { path: target, size: file.size, modified: file.modified };
+        extracted.push((info, file.sha256.clone()));
+        Ok(())
+    })?;
+
+    Ok(extracted)
+}
+
+/// Path of a file relative to a root, with `/` separators
+fn relative(root: &Path, path: &Path) -> io::Result<String> {
+    let relative = path.strip_prefix(root)
+        .map_err(|_| invalid(format!("`{}` is not under `{}`", path.display(), root.display())))?;
+
+    let parts: Vec<_> = relative.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+
+    Ok(parts.join("/"))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// An archive being written
+enum Writer {
+    Zip(Box<zip::ZipWriter<BufWriter<File>>>),
+    TarZst(tar::Builder<zstd::Encoder<'static, BufWriter<File>>>),
+}
+
+impl Writer {
+    fn new(format: Format, file: File) -> io::Result<Self> {
+        let file = BufWriter::new(file);
+
+        Ok(match format {
+            Format::Zip => Self::Zip(Box::new(zip::ZipWriter::new(file))),
+            Format::TarZst => Self::TarZst(tar::Builder::new(zstd::Encoder::new(file, ZSTD_LEVEL)?)),
+        })
+    }
+
+    fn add(&mut self, name: &str, bytes: &[u8], modified: DateTime<Local>) -> io::Result<()> {
+        match self {
+            Self::Zip(zip) => {
+                let options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .large_file(bytes.len() as u64 >= u32::MAX as u64);
+                zip.start_file(name, options)?;
+                zip.write_all(bytes)
+            },
+            Self::TarZst(tar) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(bytes.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(modified.timestamp().max(0) as u64);
+                tar.append_data(&mut header, name, bytes)
+            },
+        }
+    }
+
+    fn finish(self) -> io::Result<File> {
+        let writer = match self {
+            Self::Zip(zip) => (*zip).finish()?,
+            Self::TarZst(tar) => tar.into_inner()?.finish()?,
+        };
+
+        writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+/// Call `f` with the name and contents of each entry in an archive
+fn read_entries(path: &Path, mut f: impl FnMut(&str, &mut dyn Read) -> io::Result<()>) -> io::Result<()> {
+    let format = Format::from_path(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "archive must be a `.zip` or `.tar.zst` file"))?;
+    let file = BufReader::new(File::open(path)?);
+
+    match format {
+        Format::Zip => {
+            let mut zip = zip::ZipArchive::new(file)?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i)?;
+                let name = entry.name().to_string();
+                f(&name, &mut entry)?;
+            }
+        },
+        Format::TarZst => {
+            let mut tar = tar::Archive::new(zstd::Decoder::new(file)?);
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                let name = entry.path()?.to_string_lossy().into_owned();
+                f(&name, &mut entry)?;
+            }
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+//! Audit journal
+//!
+//! Every file removed (or quarantined, or restored) is appended to a
+//! journal that stays on disk, so that it can be shown later which files
+//! were removed, when, by whom and from which run. The journal is a
+//! JSON lines file: one [`Entry`] per line.
+//!
+//! ```text
+//! {"run":"2024-03-01_020000","time":"...","host":"HSSIENG-TASK","user":"svc_prodctrl","config":"5f1d...","action":"delete","path":"\\\\hssieng\\Jobs\\...\\part.dxf","size":48213,"modified":"...","sha256":"9c0a..."}
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wax::{Glob, Pattern};
+
+use crate::cleanup::FileInfo;
+
+/// Default journal, relative to the working directory
+pub const JOURNAL_FILE: &str = "prodctrl_audit.jsonl";
+
+/// What was done to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// the file was deleted
+    Delete,
+
+    /// the file was moved into quarantine
+    Quarantine,
+
+    /// the file was restored from quarantine
+    Restore,
+
+    /// the file was deleted after being stored in an archive
+    Archive,
+
+    /// the file was extracted from an archive
+    Extract,
+
+    /// the file was moved to cold storage
+    Move,
+}
+
+/// Information about the run that is the same for every entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    /// id of the run
+    pub run: String,
+
+    /// host the run was on
+    pub host: String,
+
+    /// user the run was as
+    pub user: String,
+
+    /// SHA-256 hash of the configuration used by the run
+    pub config: String,
+}
+
+impl Run {
+    /// Information for a run on this host as the current user
+    pub fn new(id: &str, config: &str) -> Self {
+        Self {
+            run: id.to_string(),
+            host: whoami::fallible::hostname().unwrap_or_else(|_| String::from("unknown")),
+            user: whoami::username(),
+            config: config.to_string(),
+        }
+    }
+}
+
+/// A line in the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// run that did the action
+    #[serde(flatten)]
+    pub run: Run,
+
+    /// when the action was done
+    pub time: DateTime<Local>,
+
+    /// what was done to the file
+    pub action: Action,
+
+    /// path to the file on the share (where it was removed from or restored to)
+    pub path: PathBuf,
+
+    /// size of the file, in bytes
+    pub size: u64,
+
+    /// last modified time of the file
+    pub modified: DateTime<Local>,
+
+    /// SHA-256 hash of the file contents
+    pub sha256: String,
+}
+
+/// An open journal, for appending entries for a run
+pub struct Journal {
+    file: File,
+    run: Run,
+}
+
+impl Journal {
+    /// Open (or create) a journal to append entries to
+    pub fn open(path: &Path, run: Run) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok( Self { file, run } )
+    }
+
+    /// Append an entry for a file
+    ///
+    /// `sha256` must have been computed before the action was done.
+    pub fn record(&self, action: Action, path: &Path, file: &FileInfo, sha256: String) -> io::Result<()> {
+        let entry = Entry {
+            run: self.run.clone(),
+            time: Local::now(),
+            action,
+            path: path.to_path_buf(),
+            size: file.size,
+            modified: file.modified,
+            sha256,
+        };
+
+        // each entry is written in a single call, so that entries are whole lines
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        (&self.file).write_all(line.as_bytes())
+    }
+}
+
+/// Read every entry in a journal
+///
+/// Lines that cannot be parsed are skipped (with a warning).
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        match serde_json::from_str(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => log::warn!("Skipping line {} of `{}`: {}", i + 1, path.display(), e),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Filter for searching the journal
+#[derive(Debug, Default)]
+pub struct Query {
+    /// only entries from this run
+    pub run: Option<String>,
+
+    /// only entries for this action
+    pub action: Option<Action>,
+
+    /// only entries whose path matches this glob
+    pub path: Option<Glob<'static>>,
+
+    /// only entries from this user
+    pub user: Option<String>,
+
+    /// only entries at or after this time
+    pub since: Option<DateTime<Local>>,
+
+    /// only entries before this time
+    pub until: Option<DateTime<Local>>,
+}
+
+impl Query {
+    /// Whether an entry matches every part of the query
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.run.as_ref().is_none_or(|run| *run == entry.run.run)
+            && self.action.is_none_or(|action| action == entry.action)
+            && self.path.as_ref().is_none_or(|glob| glob.is_match(entry.path.as_path()))
+            && self.user.as_ref().is_none_or(|user| user.eq_ignore_ascii_case(&entry.run.user))
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time < until)
+    }
+}
+
+/// SHA-256 hash of a file's contents, as a hex string
+pub fn sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok( format!("{:x}", hasher.finalize()) )
+}
+
+/// SHA-256 hash of some bytes, as a hex string
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
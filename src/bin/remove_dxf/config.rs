@@ -0,0 +1,338 @@
+//! Configuration file
+//!
+//! Roots to search, the cleanup rules used for each root and any
+//! changes to those rules' retention age and patterns are read from a
+//! TOML file. Settings are looked up from the most specific place
+//! first: the root's rule table, the root, the top-level rule table and
+//! then the top level, falling back to the rule's defaults (see
+//! [`prodctrl::cleanup::rules`]). Anything set for a root always wins
+//! over the same setting for all roots.
+//!
+//! ```toml
+//! retention_days = 60
+//! journal = '\\hssieng\Jobs\_prodctrl\audit.jsonl'
+//! protected_jobs = '\\hssieng\Jobs\_prodctrl\protected.txt'
+//! exclude = ["*/Warranty", "**/Fab/**/Refab"]
+//! history = '\\hssieng\Jobs\_prodctrl\history.jsonl'
+//!
+//! [limits]
+//! max_files = 5000
+//! max_bytes = 20_000_000_000
+//! max_growth = 3.0
+//!
+//! [in_use]
+//! quiet_minutes = 60
+//! lock_files = ["*.lck"]
+//!
+//! [rules.dxf]
+//! folders = "**/Fab/**/DXF"
+//! files = "(?i)*.dxf"
+//!
+//! [[root]]
+//! name = "jobs"
+//! path = '\\hssieng\Jobs'
+//!
+//! [[root]]
+//! name = "mnt"
+//! path = "/mnt/jobs"
+//! retention_days = 90
+//! exclude = ["1190*"]
+//!
+//! [root.guard]
+//! min_jobs = 5
+//!
+//! [root.rules.dxf]
+//! [root.rules.nc]
+//! retention_days = 365
+//! ```
+//!
+//! A root without a `rules` table only uses the `dxf` rule. If no
+//! configuration file is found, only the `dxf` rule is used on the
+//! `\\hssieng\Jobs` root.
+//!
+//! Protected jobs (see [`prodctrl::jobs::read_list`]), folders matching
+//! an `exclude` glob (relative to the root) and folders holding a
+//! `.keep` file are never searched.
+//!
+//! Before a root is searched, it is checked to really be the Jobs share
+//! (see [`prodctrl::guard`]); the checks can be changed in the root's
+//! `guard` table.
+//!
+//! A run stops before removing anything if it goes over any of the
+//! `limits` (see [`prodctrl::breaker`]). Files that look to be in use
+//! are left for a later run, as set in the `in_use` table (see
+//! [`prodctrl::in_use`]).
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use prodctrl::audit;
+use prodctrl::breaker::{self, Limits};
+use prodctrl::cleanup::{rules, Exclusions, Rule};
+use prodctrl::guard::Guard;
+use prodctrl::in_use::InUse;
+use prodctrl::jobs;
+
+/// Default configuration file, relative to the working directory
+pub const CONFIG_FILE: &str = "prodctrl.toml";
+
+const ROOT_DIR: &str = r"\\hssieng\Jobs";
+const DEFAULT_RULE: &str = "dxf";
+
+/// Configuration file contents
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// days to keep files before they are eligible for deletion
+    pub retention_days: Option<u64>,
+
+    /// audit journal to append to (see [`prodctrl::audit`])
+    pub journal: PathBuf,
+
+    /// file listing jobs that are never cleaned up
+    pub protected_jobs: Option<PathBuf>,
+
+    /// globs (relative to a root) matching folders that are never searched
+    pub exclude: Vec<String>,
+
+    /// history of past runs, for [`Limits::max_growth`]
+    pub history: PathBuf,
+
+    /// limits on what a run can remove
+    pub limits: Limits,
+
+    /// checks for files that are still in use
+    pub in_use: InUse,
+
+    /// SHA-256 hash of the configuration file (`default` if there is none)
+    #[serde(skip)]
+    pub hash: String,
+
+    /// changes to rule settings, for all roots
+    pub rules: BTreeMap<String, RuleConfig>,
+
+    /// roots to search
+    #[serde(rename = "root")]
+    pub roots: Vec<RootConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            retention_days: None,
+            journal: PathBuf::from(audit::JOURNAL_FILE),
+            protected_jobs: None,
+            exclude: Vec::new(),
+            history: PathBuf::from(breaker::HISTORY_FILE),
+            limits: Limits::default(),
+            in_use: InUse::default(),
+            hash: String::from("default"),
+            rules: BTreeMap::new(),
+            roots: vec![
+                RootConfig {
+                    name: String::from("jobs"),
+                    path: PathBuf::from(ROOT_DIR),
+                    retention_days: None,
+                    exclude: Vec::new(),
+                    guard: Guard::default(),
+                    rules: None,
+                }
+            ],
+        }
+    }
+}
+
+/// Settings for a single root, as written in the configuration file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RootConfig {
+    /// short name of the root (used for quarantine folders)
+    pub name: String,
+
+    /// directory to search
+    pub path: PathBuf,
+
+    /// overrides [`Config::retention_days`]
+    pub retention_days: Option<u64>,
+
+    /// added to [`Config::exclude`]
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// checks that the root is the Jobs share
+    #[serde(default)]
+    pub guard: Guard,
+
+    /// rules used for this root, and changes to their settings
+    pub rules: Option<BTreeMap<String, RuleConfig>>,
+}
+
+/// Changes to a rule's settings
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuleConfig {
+    /// days to keep files before they are eligible for deletion
+    pub retention_days: Option<u64>,
+
+    /// glob (relative to a root) matching folders to search
+    pub folders: Option<String>,
+
+    /// glob (relative to a matched folder) matching files to delete
+    pub files: Option<String>,
+}
+
+/// A root and the rules used for it, with settings applied
+pub struct Root {
+    /// short name of the root
+    pub name: String,
+
+    /// directory to search
+    pub path: PathBuf,
+
+    /// rules used for this root
+    pub rules: Vec<Rule>,
+
+    /// subtrees that are never searched
+    pub exclusions: Exclusions,
+
+    /// checks that the root is the Jobs share
+    pub guard: Guard,
+}
+
+impl Config {
+    /// Read the configuration file
+    ///
+    /// If no path is given, [`CONFIG_FILE`] is used if it exists and
+    /// the default configuration otherwise.
+    pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(CONFIG_FILE).is_file() => Path::new(CONFIG_FILE),
+            None => {
+                log::info!("No configuration file found, using defaults");
+                return Ok(Self::default());
+            },
+        };
+
+        log::info!("Reading configuration from `{}`", path.display());
+        let text = fs::read_to_string(path)?;
+        let mut config: Self = toml::from_str(&text)?;
+        config.hash = audit::sha256_bytes(text.as_bytes());
+
+        if config.roots.is_empty() {
+            return Err(format!("no roots configured in `{}`", path.display()).into());
+        }
+
+        Ok(config)
+    }
+
+    /// Roots to search, with rule settings applied
+    pub fn roots(&self) -> Result<Vec<Root>, Box<dyn Error>> {
+        let default_rules = BTreeMap::from([(String::from(DEFAULT_RULE), RuleConfig::default())]);
+        let no_changes = RuleConfig::default();
+        let protected = match &self.protected_jobs {
+            Some(path) => {
+                let protected = jobs::read_list(path)
+                    .map_err(|e| format!("could not read protected jobs from `{}`: {}", path.display(), e))?;
+                log::info!("{} protected jobs listed in `{}`", protected.len(), path.display());
+                protected
+            },
+            None => Default::default(),
+        };
+
+        let mut roots = Vec::new();
+        for root in &self.roots {
+            let mut rules = Vec::new();
+            for (name, config) in root.rules.as_ref().unwrap_or(&default_rules) {
+                let rule = rules::by_name(name)
+                    .ok_or_else(|| format!("unknown rule `{}` (expected one of {})", name, rules::NAMES.join(", ")))?;
+                let global = self.rules.get(name).unwrap_or(&no_changes);
+
+                let mut settings = rule.defaults();
+                if let Some(days) = [config.retention_days, root.retention_days, global.retention_days, self.retention_days].into_iter().flatten().next() {
+                    settings.retention = self::days(days);
+                }
+                if let Some(folders) = config.folders.as_ref().or(global.folders.as_ref()) {
+                    settings.folders = folders.clone();
+                }
+                if let Some(files) = config.files.as_ref().or(global.files.as_ref()) {
+                    settings.files = files.clone();
+                }
+
+                rules.push(Rule::new(rule, settings)?);
+            }
+
+            let exclude = [self.exclude.as_slice(), root.exclude.as_slice()].concat();
+            let exclusions = Exclusions::new(protected.clone(), &exclude)?;
+
+            roots.push(Root { name: root.name.clone(), path: root.path.clone(), rules, exclusions, guard: root.guard.clone() });
+        }
+
+        Ok(roots)
+    }
+}
+
+/// Convert a number of days into a [`Duration`]
+pub fn days(days: u64) -> Duration {
+    Duration::from_secs(days * 24 * 60 * 60)    // days * hours * minutes * seconds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retention(toml: &str) -> Vec<Duration> {
+        let config: Config = toml::from_str(toml).unwrap();
+
+        config.roots().unwrap().iter()
+            .map(|root| root.rules[0].retention())
+            .collect()
+    }
+
+    #[test]
+    fn root_retention_wins_over_global_rule() {
+        let toml = r#"
+            retention_days = 30
+
+            [rules.dxf]
+            retention_days = 60
+
+            [[root]]
+            name = "a"
+            path = "/a"
+            retention_days = 365
+
+            [[root]]
+            name = "b"
+            path = "/b"
+
+            [[root]]
+            name = "c"
+            path = "/c"
+            retention_days = 365
+
+            [root.rules.dxf]
+            retention_days = 7
+        "#;
+
+        assert_eq!(retention(toml), [days(365), days(60), days(7)]);
+    }
+
+    #[test]
+    fn top_level_retention() {
+        let toml = r#"
+            retention_days = 30
+
+            [[root]]
+            name = "a"
+            path = "/a"
+        "#;
+
+        assert_eq!(retention(toml), [days(30)]);
+    }
+}
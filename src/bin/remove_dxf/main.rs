@@ -0,0 +1,1095 @@
+
+//! Remove DXF files (and other old files on the Jobs share)
+//! 
+//! This takes care of the problem of DXF files that are used as an
+//! intermediate format between NX and Sigmanest taking up too much
+//! server space. These files are used so that we do not version
+//! lock the two softwares.
+//! 
+//! This utility, broadly speaking, finds any file matching the glob
+//! pattern `\\hssieng\Jobs\**\Fab\**\DXF\*.dxf` where
+//! - The file is older than 60 days
+//! - The file also has an associated `.log` file
+//! 
+//! The roots searched, the retention age and the patterns used are
+//! set in a configuration file (see [`config`]); the above are the
+//! defaults. Other kinds of files (NC programs, superseded PDFs and
+//! STEP exports) can be cleaned up as well by enabling their rules
+//! (see [`prodctrl::cleanup::rules`]).
+//! 
+//! The age of each file is checked individually, using the modified
+//! time of the `.dxf` file, the `.log` file or the newer of the two
+//! (see `--age-from`).
+//! 
+//! This ensures that we are not deleted DXF files that are not yet
+//! imported, as well as ones that did not originate from NX (generally,
+//! only the NX generated DXF's will have an associated `.log` file.
+//! The found files are then deleted.
+//! 
+//! Passing `--dry-run` (without a subcommand) runs the same search but
+//! deletes nothing. Instead, a manifest of the files that would have
+//! been deleted is written (see [`manifest`]) so that it can be
+//! reviewed first.
+//! 
+//! For a reviewed cleanup, use `plan` to write a plan file and then
+//! `apply` to delete exactly the files in that plan (see [`plan`]).
+//! 
+//! Passing `--quarantine <DIR>` moves files into a quarantine folder
+//! instead of deleting them, from which they can be put back with
+//! `restore` until they are purged (see [`quarantine`]).
+//! 
+//! `archive` packs the files into a compressed archive for each job
+//! instead, checks that the archive reads back and only then deletes
+//! them. `extract` takes files back out of an archive (see
+//! [`prodctrl::archive`]).
+//! 
+//! Passing `--tier <DIR>` moves files to a cold-storage root instead,
+//! keeping their layout, and `--stub` leaves a note where each file was
+//! (see [`tier`]).
+//! 
+//! Every file that is removed or restored is recorded in the audit
+//! journal (see [`prodctrl::audit`]), which can be searched with
+//! `prodctrl audit query`.
+//! 
+//! `orphans` lists `.log` files without a `.dxf` file and `.dxf` files
+//! without a `.log` file, and can clean up the orphaned `.log` files
+//! (see [`orphans`]).
+//! 
+//! At the end of a run, the space reclaimed is shown for each job and
+//! `Fab` subfolder, and can be written out with `--report` (see
+//! [`prodctrl::report`]).
+//! 
+//! Job folders are searched by `--workers` threads at once. To keep
+//! the load on the file server down (i.e. during shift hours), the
+//! filesystem operations of a run can be capped with `--max-ops` (see
+//! [`prodctrl::throttle`]).
+//! 
+//! Passing `--index <FILE>` keeps an index of the folders searched, so
+//! that folders which have not changed since the last run are not
+//! listed again (see [`prodctrl::cleanup::index`]).
+//! 
+//! Each root is checked to really be the Jobs share before it is
+//! searched or anything is removed from it, so that an unmounted share
+//! or a mistyped path aborts the run (see [`prodctrl::guard`]).
+//! 
+//! A run is stopped before anything is removed if it would go over the
+//! configured limits, such as the most files a run can remove or a jump
+//! in files from past runs (see [`prodctrl::breaker`]).
+//! 
+//! Files that look to be in use by NX or Sigmanest (i.e. a file in the
+//! folder was just modified, or a lock file is present) are not removed,
+//! but left for a later run and listed in the report (see
+//! [`prodctrl::in_use`]).
+//! 
+//! Passing `--remove-empty-dirs` also removes the folders a run leaves
+//! empty (i.e. `DXF` folders), and the folders above them that end up
+//! empty, up to `--empty-dir-depth` folders in all. Only folders under a
+//! job's `Fab` folder are ever removed.
+//! 
+//! Errors (such as permission denials, locked files or a disconnected
+//! share) do not stop a run, but are listed in its summary. The run
+//! exits with a non-zero code if there are more than `--max-errors`.
+
+
+mod config;
+mod manifest;
+mod orphans;
+mod plan;
+mod quarantine;
+mod tier;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use chrono::Local;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use wax::Glob;
+
+use prodctrl::archive;
+use prodctrl::audit::{self, Action, Journal};
+use prodctrl::breaker::{self, RunSummary, Trip};
+use prodctrl::cleanup::{self, Candidate, FileInfo, Found, KeepReason, Orphan};
+use prodctrl::cleanup::index::Index;
+use prodctrl::error;
+use prodctrl::in_use::Activity;
+use prodctrl::paths::JobPath;
+use prodctrl::report::Report;
+use prodctrl::throttle::Throttle;
+
+use config::{Config, Root};
+use orphans::Orphans;
+use plan::Plan;
+use quarantine::Quarantine;
+use tier::Tier;
+
+
+#[derive(Debug, Parser)]
+#[command(name = "hss_pc_dxf_remover", version, about = "Remove old NX DXF files from the Jobs share")]
+struct Args {
+    /// Configuration file [default: prodctrl.toml, if it exists]
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Do not delete anything; write a manifest of the files that would be deleted
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Manifest path (`.csv` and `.json` files are written)
+    #[arg(long, default_value = "dxf_manifest")]
+    manifest: PathBuf,
+
+    /// Which modified time to use for the age of a file and its companions
+    #[arg(long, value_enum, global = true, default_value_t = AgeFrom::Newest)]
+    age_from: AgeFrom,
+
+    /// Move files into this quarantine folder instead of deleting them
+    #[arg(long, global = true)]
+    quarantine: Option<PathBuf>,
+
+    /// Move files to this cold-storage root instead of deleting them
+    #[arg(long, global = true, conflicts_with = "quarantine")]
+    tier: Option<PathBuf>,
+
+    /// Leave a stub file pointing to where each file was moved (with `--tier`)
+    #[arg(long, global = true, requires = "tier")]
+    stub: bool,
+
+    /// Days to keep quarantined files before they are purged
+    #[arg(long, global = true, default_value_t = 30)]
+    grace_days: u64,
+
+    /// Audit journal to append to [default: from the configuration file]
+    #[arg(long, global = true)]
+    journal: Option<PathBuf>,
+
+    /// Write a report of the space reclaimed (`.csv` and `.json` files are written)
+    #[arg(long, global = true)]
+    report: Option<PathBuf>,
+
+    /// Number of job folders to search at once
+    #[arg(long, global = true, default_value_t = 4)]
+    workers: usize,
+
+    /// Maximum filesystem operations per second, when searching and removing files [default: no limit]
+    #[arg(long, global = true)]
+    max_ops: Option<u32>,
+
+    /// Keep an index of searched folders in this file, and skip listing unchanged folders
+    #[arg(long, global = true)]
+    index: Option<PathBuf>,
+
+    /// Remove folders left empty by a run (and the folders above them that end up empty)
+    #[arg(long, global = true)]
+    remove_empty_dirs: bool,
+
+    /// Most folders to remove going up from each emptied folder (including it)
+    #[arg(long, global = true, default_value_t = 2)]
+    empty_dir_depth: usize,
+
+    /// Exit with an error code if a run has more than this many errors
+    #[arg(long, global = true, default_value_t = 0)]
+    max_errors: usize,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Write a plan of the files that would be deleted, for review
+    Plan {
+        /// Plan file to write
+        #[arg(short, long, default_value = "dxf_plan.json")]
+        output: PathBuf,
+    },
+
+    /// Delete the files listed in a plan
+    Apply {
+        /// Plan file to apply
+        plan: PathBuf,
+
+        /// Apply the plan even if it went over the configured limits
+        #[arg(long)]
+        override_limits: bool,
+    },
+
+    /// Put quarantined files back where they came from
+    #[command(group = clap::ArgGroup::new("selection").required(true).multiple(true))]
+    Restore {
+        /// Restore files from this run
+        #[arg(long, group = "selection")]
+        run: Option<String>,
+
+        /// Restore files from this job
+        #[arg(long, group = "selection")]
+        job: Option<String>,
+
+        /// Restore files whose path (relative to its root) matches this glob
+        #[arg(long, group = "selection")]
+        glob: Option<String>,
+    },
+
+    /// Delete quarantined files older than the grace period
+    Purge,
+
+    /// Pack files into a compressed archive for each job, then delete them
+    Archive {
+        /// Archive format
+        #[arg(long, value_enum, default_value_t = ArchiveFormat::TarZst)]
+        format: ArchiveFormat,
+
+        /// Keep archives under this folder (i.e. a cold-storage root) [default: an `_archive` folder in each job]
+        #[arg(long)]
+        to: Option<PathBuf>,
+    },
+
+    /// Take files back out of an archive
+    Extract {
+        /// Archive to extract from
+        archive: PathBuf,
+
+        /// Extract into this folder [default: the root the files came from]
+        #[arg(long)]
+        to: Option<PathBuf>,
+
+        /// Only extract files whose path (relative to its root) matches this glob
+        #[arg(long)]
+        glob: Option<String>,
+    },
+
+    /// List `.log` files without a `.dxf` file and `.dxf` files without a `.log` file
+    Orphans {
+        /// Write the list to this CSV file
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Remove orphaned `.log` files older than the retention age
+        #[arg(long)]
+        clean: bool,
+    },
+}
+
+/// Modified time used for the age of a file and its companions
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum AgeFrom {
+    /// modified time of the file (i.e. the `.dxf` file)
+    #[value(alias = "dxf")]
+    File,
+
+    /// modified time of the companion file (i.e. the `.log` file)
+    #[value(alias = "log")]
+    Companion,
+
+    /// newest modified time of the file and its companions
+    Newest,
+}
+
+/// Kind of archive to pack files into
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ArchiveFormat {
+    /// zip archive
+    Zip,
+
+    /// tar archive, compressed with zstd
+    TarZst,
+}
+
+impl From<ArchiveFormat> for archive::Format {
+    fn from(value: ArchiveFormat) -> Self {
+        match value {
+            ArchiveFormat::Zip => Self::Zip,
+            ArchiveFormat::TarZst => Self::TarZst,
+        }
+    }
+}
+
+impl From<AgeFrom> for cleanup::AgeFrom {
+    fn from(value: AgeFrom) -> Self {
+        match value {
+            AgeFrom::File => Self::File,
+            AgeFrom::Companion => Self::Companion,
+            AgeFrom::Newest => Self::Newest,
+        }
+    }
+}
+
+fn main() -> Result<ExitCode, Box<dyn Error>> {
+    pretty_env_logger::init();
+    let args = Args::parse();
+
+    // subcommands do their own thing, so a dry run must not look like it applies to them
+    if args.dry_run && args.command.is_some() {
+        Args::command()
+            .error(clap::error::ErrorKind::ArgumentConflict, "`--dry-run` cannot be used with a subcommand")
+            .exit();
+    }
+
+    let config = Config::load(args.config.as_deref())?;
+    let roots = config.roots()?;
+
+    // never search (or remove from) a root that is not the Jobs share
+    if !matches!(args.command, Some(Command::Restore { .. }) | Some(Command::Purge) | Some(Command::Extract { .. })) {
+        for root in &roots {
+            let jobs = root.guard.check(&root.path)
+                .map_err(|e| format!("root `{}` failed its safety checks: {}", root.name, e))?;
+            log::info!("Root `{}` has {} job folders", root.name, jobs);
+        }
+    }
+
+    let run = Local::now().format("%Y-%m-%d_%H%M%S").to_string();
+
+    let throttle = Throttle::new(args.max_ops.unwrap_or(0));
+    let mut errors = Vec::new();
+    let mut report = Report::new(roots.iter().map(|root| root.path.clone()).collect());
+    let activity = config.in_use.activity(&throttle)?;
+    let grace = config::days(args.grace_days);
+
+    // archives are always deleted from once they are verified
+    if matches!(args.command, Some(Command::Archive { .. })) {
+        if args.quarantine.is_some() {
+            return Err("`--quarantine` cannot be used with `archive`".into());
+        }
+        if args.tier.is_some() {
+            return Err("`--tier` cannot be used with `archive`".into());
+        }
+    }
+
+    // the journal is only opened by the commands that remove or restore files
+    let journal = |what: &str| -> Result<Journal, Box<dyn Error>> {
+        let path = args.journal.as_ref().unwrap_or(&config.journal);
+        log::info!("{} run {} (audit journal `{}`)", what, run, path.display());
+
+        Ok( Journal::open(path, audit::Run::new(&run, &config.hash))? )
+    };
+    let storage = Storage { quarantine: args.quarantine.as_deref(), tier: args.tier.as_deref(), stub: args.stub, grace };
+
+    match args.command {
+        Some(Command::Plan { output }) => {
+            let found = search(&roots, args.age_from, args.workers, &throttle, args.index.as_deref(), &mut errors);
+            let trips = check_limits(&config, &roots, &found, false)?;
+
+            let plan = Plan::new(&roots, found.candidates, &trips);
+            plan.write(&output)?;
+
+            log::info!("Planned deletion of {} files (plan written to `{}`)", plan.files.len(), output.display());
+        },
+
+        Some(Command::Apply { plan, override_limits }) => {
+            let plan = Plan::read(&plan)?;
+            plan.check_roots(&roots)?;
+            log::info!("Applying plan created {}", plan.created);
+
+            for trip in &plan.trips {
+                match override_limits {
+                    true => log::warn!("Over limit when planned (overridden): {}", trip),
+                    false => log::error!("Over limit when planned: {}", trip),
+                }
+            }
+            if !plan.trips.is_empty() && !override_limits {
+                return Err(format!("plan went over {} limits, pass `--override-limits` to apply it", plan.trips.len()).into());
+            }
+
+            let files: Vec<Candidate> = plan.files.into_iter()
+                .filter(|candidate| match candidate.changed() {
+                    Some(path) => {
+                        log::warn!("Skipping `{}` (changed since the plan was made)", path.display());
+                        false
+                    },
+                    None => true,
+                })
+                .collect();
+            let files = protected(files, &roots, &mut errors);
+
+            trip(config.limits.check_totals(&files), !override_limits)?;
+
+            let journal = journal("Apply")?;
+            let destination = storage.open(&run, &roots, &journal, &mut errors)?;
+            let deleted = remove_files(&files, destination.removal(), &journal, &activity, &throttle, &mut report, &mut errors);
+            log::info!("Deleted {} files", deleted);
+
+            if args.remove_empty_dirs {
+                let removed = remove_empty_dirs(&files, &roots, args.empty_dir_depth, &throttle, &mut errors);
+                log::info!("Removed {} empty folders", removed);
+            }
+            record_run(&config, &run, &report);
+        },
+
+        Some(Command::Restore { run, job, glob }) => {
+            let quarantine = args.quarantine.as_deref().ok_or("`--quarantine` is required")?;
+            let selection = quarantine::Selection { run, job, glob };
+            let restored = quarantine::restore(quarantine, &selection, &journal("Restore")?, &mut errors)?;
+
+            log::info!("Restored {} files", restored);
+        },
+
+        Some(Command::Purge) => {
+            let quarantine = args.quarantine.as_deref().ok_or("`--quarantine` is required")?;
+            let purged = quarantine::purge(quarantine, grace, &journal("Purge")?, &mut errors)?;
+            log::info!("Purged {} expired quarantine runs", purged);
+        },
+
+        Some(Command::Archive { format, to }) => {
+            let found = search(&roots, args.age_from, args.workers, &throttle, args.index.as_deref(), &mut errors);
+            check_limits(&config, &roots, &found, true)?;
+
+            let target = Target { format: format.into(), roots: &roots, to: to.as_deref(), run: &run };
+            let archived = archive_files(&found.candidates, &target, &journal("Archive")?, &activity, &throttle, &mut report, &mut errors);
+            log::info!("Archived {} files", archived);
+
+            if args.remove_empty_dirs {
+                let removed = remove_empty_dirs(&found.candidates, &roots, args.empty_dir_depth, &throttle, &mut errors);
+                log::info!("Removed {} empty folders", removed);
+            }
+            record_run(&config, &run, &report);
+        },
+
+        Some(Command::Extract { archive: path, to, glob }) => {
+            let dest = match to {
+                Some(to) => to,
+                None => archive::read_manifest(&path)?.root,
+            };
+            let glob = glob.as_deref().map(Glob::new).transpose()?;
+
+            let journal = journal("Extract")?;
+            let extracted = archive::extract(&path, &dest, glob.as_ref())?;
+            for (file, sha256) in extracted.iter() {
+                if let Err(e) = journal.record(Action::Extract, &file.path, file, sha256.clone()) {
+                    log::error!("Failed to record `{}` in the audit journal: {}", file.path.display(), e);
+                    errors.push(error::Error::new(&file.path, e));
+                }
+            }
+
+            log::info!("Extracted {} files to `{}`", extracted.len(), dest.display());
+        },
+
+        Some(Command::Orphans { output, clean }) => {
+            let found = search(&roots, args.age_from, args.workers, &throttle, args.index.as_deref(), &mut errors);
+
+            let orphans = Orphans::new(&found);
+            println!("{}", orphans);
+            if let Some(path) = &output {
+                orphans.write(path)?;
+                log::info!("Orphans written to `{}`", path.display());
+            }
+
+            if clean {
+                let files: Vec<Candidate> = found.orphans.iter()
+                    .filter(|orphan| orphan.expired)
+                    .map(Orphan::to_candidate)
+                    .collect();
+                trip(config.limits.check_totals(&files), true)?;
+
+                let journal = journal("Orphan cleanup")?;
+                let destination = storage.open(&run, &roots, &journal, &mut errors)?;
+                let removed = remove_files(&files, destination.removal(), &journal, &activity, &throttle, &mut report, &mut errors);
+                log::info!("Removed {} orphaned companion files", removed);
+
+                if args.remove_empty_dirs {
+                    let removed = remove_empty_dirs(&files, &roots, args.empty_dir_depth, &throttle, &mut errors);
+                    log::info!("Removed {} empty folders", removed);
+                }
+            }
+        },
+
+        None if args.dry_run => {
+            let found = search(&roots, args.age_from, args.workers, &throttle, args.index.as_deref(), &mut errors);
+            check_limits(&config, &roots, &found, false)?;
+
+            let files = found.candidates;
+            manifest::write(&args.manifest, &files)?;
+            files.iter().for_each(|candidate| report.add(candidate));
+
+            log::info!("Dry run: {} files would be deleted (manifest written to `{}`)", files.len(), args.manifest.display());
+        },
+
+        None => {
+            let found = search(&roots, args.age_from, args.workers, &throttle, args.index.as_deref(), &mut errors);
+            check_limits(&config, &roots, &found, true)?;
+
+            let journal = journal("Cleanup")?;
+            let destination = storage.open(&run, &roots, &journal, &mut errors)?;
+            let deleted = remove_files(&found.candidates, destination.removal(), &journal, &activity, &throttle, &mut report, &mut errors);
+            log::info!("Deleted {} files", deleted);
+
+            if args.remove_empty_dirs {
+                let removed = remove_empty_dirs(&found.candidates, &roots, args.empty_dir_depth, &throttle, &mut errors);
+                log::info!("Removed {} empty folders", removed);
+            }
+            record_run(&config, &run, &report);
+        },
+    }
+
+    if report.total.files > 0 || report.deferred.files > 0 {
+        println!("{}", report);
+    }
+    if let Some(path) = &args.report {
+        report.write(path)?;
+        log::info!("Report written to `{}`", path.display());
+    }
+
+    report_errors(&errors);
+    match errors.len() > args.max_errors {
+        true => {
+            log::error!("Run had {} errors (more than the {} allowed)", errors.len(), args.max_errors);
+            Ok(ExitCode::FAILURE)
+        },
+        false => Ok(ExitCode::SUCCESS),
+    }
+}
+
+/// Log the errors from a run, with a count of each kind
+fn report_errors(errors: &[error::Error]) {
+    if errors.is_empty() {
+        return;
+    }
+
+    let mut counts = BTreeMap::new();
+    for error in errors {
+        log::warn!("{}", error);
+        *counts.entry(error.kind).or_insert(0) += 1;
+    }
+
+    let counts: Vec<String> = counts.iter()
+        .map(|(kind, count)| format!("{} {}", count, kind))
+        .collect();
+    log::warn!("{} errors ({})", errors.len(), counts.join(", "));
+}
+
+/// Find all files that qualify for deletion
+fn search(roots: &[Root], age_from: AgeFrom, workers: usize, throttle: &Throttle, index_file: Option<&Path>, errors: &mut Vec<error::Error>) -> Found {
+    let mut found = Found::default();
+
+    // a bad index only costs time, so the search goes ahead without it
+    let index = index_file.map(|path| Index::load(path).unwrap_or_else(|e| {
+        log::warn!("Could not read index `{}`, listing all folders: {}", path.display(), e);
+        Index::default()
+    }));
+
+    for root in roots {
+        let names: Vec<_> = root.rules.iter().map(|rule| rule.name()).collect();
+        log::info!("Searching root `{}` ({}) for {} files", root.name, root.path.display(), names.join(", "));
+        found.extend(cleanup::search(&root.path, &root.rules, age_from.into(), &root.exclusions, workers, throttle, index.as_ref()));
+    }
+
+    if let (Some(index), Some(path)) = (&index, index_file) {
+        if let Err(e) = index.save(path) {
+            log::warn!("Could not write index `{}`: {}", path.display(), e);
+        }
+    }
+
+    // i.e. DXF files without a .log file did not come from NX, so they are kept
+    let (mut unpaired, mut flagged) = (0, 0);
+    for kept in &found.kept {
+        match kept.reason {
+            KeepReason::Flagged(_) => {
+                log::warn!("Keeping `{}` ({})", kept.path.display(), kept.reason);
+                flagged += 1;
+            },
+            KeepReason::MissingCompanion(_) => {
+                log::info!("Keeping `{}` ({})", kept.path.display(), kept.reason);
+                unpaired += 1;
+            },
+            KeepReason::Rule(_) => log::info!("Keeping `{}` ({})", kept.path.display(), kept.reason),
+        }
+    }
+    log::info!("Found {} files to delete ({} files kept: {} unpaired, {} flagged; {} orphaned companion files)", found.candidates.len(), found.kept.len(), unpaired, flagged, found.orphans.len());
+
+    errors.append(&mut found.errors);
+
+    found
+}
+
+/// Check the results of a search against the configured limits
+///
+/// If `enforce` is set, going over any limit is an error; otherwise it
+/// is only logged (i.e. for a dry run, so the manifest can be reviewed).
+/// Leave out files that were protected (or excluded, or marked to keep)
+/// since a plan was made
+fn protected(files: Vec<Candidate>, roots: &[Root], errors: &mut Vec<error::Error>) -> Vec<Candidate> {
+    let mut skipped = 0;
+    let files: Vec<Candidate> = files.into_iter()
+        .filter(|candidate| {
+            let Some(root) = roots.iter().find(|root| candidate.file.path.starts_with(&root.path)) else { return false };
+            for file in candidate.files() {
+                match root.exclusions.check(&root.path, &file.path) {
+                    Ok(None) => (),
+                    Ok(Some(reason)) => {
+                        log::warn!("Skipping `{}` ({})", candidate.file.path.display(), reason);
+                        skipped += 1;
+                        return false;
+                    },
+                    Err(e) => {
+                        errors.push(error::Error::new(&file.path, e));
+                        return false;
+                    },
+                }
+            }
+
+            true
+        })
+        .collect();
+
+    if skipped > 0 {
+        log::warn!("Skipped {} files in the plan that are now protected", skipped);
+    }
+
+    files
+}
+
+fn check_limits(config: &Config, roots: &[Root], found: &Found, enforce: bool) -> Result<Vec<Trip>, Box<dyn Error>> {
+    let history = breaker::read_history(&config.history)?;
+    let roots: Vec<PathBuf> = roots.iter().map(|root| root.path.clone()).collect();
+
+    trip(config.limits.check(found, &roots, &history), enforce)
+}
+
+fn trip(trips: Vec<Trip>, enforce: bool) -> Result<Vec<Trip>, Box<dyn Error>> {
+    for trip in &trips {
+        match enforce {
+            true => log::error!("Over limit: {}", trip),
+            false => log::warn!("Over limit: {}", trip),
+        }
+    }
+
+    match enforce && !trips.is_empty() {
+        true => Err(format!("run went over {} limits, nothing was removed", trips.len()).into()),
+        false => Ok( trips ),
+    }
+}
+
+/// Add a finished run to the history of past runs
+fn record_run(config: &Config, run: &str, report: &Report) {
+    let summary = RunSummary::new(run, report.total.files, report.total.bytes);
+    if let Err(e) = breaker::append_history(&config.history, &summary) {
+        log::error!("Failed to record the run in `{}`: {}", config.history.display(), e);
+    }
+}
+
+/// Where each job's archive is written
+struct Target<'a> {
+    format: archive::Format,
+
+    /// roots the files came from
+    roots: &'a [Root],
+
+    /// folder to keep archives under, instead of in each job
+    to: Option<&'a Path>,
+
+    /// run, used for the archive file name
+    run: &'a str,
+}
+
+impl Target<'_> {
+    /// Archive for a job (i.e. `<root>\1210123\_archive\<run>.tar.zst`,
+    /// or `<to>\<root name>\1210123\<run>.tar.zst`)
+    fn path(&self, root: &Root, job: &str) -> PathBuf {
+        let dir = match self.to {
+            Some(to) => to.join(&root.name).join(job),
+            None => root.path.join(job).join(archive::ARCHIVE_DIR),
+        };
+
+        dir.join(format!("{}.{}", self.run, self.format.extension()))
+    }
+}
+
+/// Pack files into an archive for each job, and delete them once the
+/// archive has been read back and checked
+///
+/// If a job's archive cannot be written or does not check out, none of
+/// the job's files are deleted.
+fn archive_files(files: &[Candidate], target: &Target, journal: &Journal, activity: &Activity, throttle: &Throttle, report: &mut Report, errors: &mut Vec<error::Error>) -> u32 {
+    let mut jobs: BTreeMap<(usize, String), Vec<&Candidate>> = BTreeMap::new();
+    for candidate in files {
+        // files in use are left out of the archive too
+        if let Some(busy) = activity.check(candidate, errors) {
+            log::warn!("Deferring `{}` (in use: {})", candidate.file.path.display(), busy);
+            report.defer(candidate);
+            continue;
+        }
+
+        let job = target.roots.iter().enumerate()
+            .find_map(|(i, root)| JobPath::parse(&root.path, &candidate.file.path).ok().map(|path| (i, path.job)));
+        match job {
+            Some(job) => jobs.entry(job).or_default().push(candidate),
+            None => log::warn!("Keeping `{}` (not in a job folder)", candidate.file.path.display()),
+        }
+    }
+
+    let mut archived = 0;
+    for ((root, job), candidates) in jobs {
+        let root = &target.roots[root];
+        let path = target.path(root, &job);
+
+        let infos: Vec<&FileInfo> = candidates.iter().flat_map(|candidate| candidate.files()).collect();
+        infos.iter().for_each(|_| throttle.wait());
+
+        let result = archive::create(&path, target.run, &root.path, &infos)
+            .and_then(|manifest| archive::verify(&path, &manifest).map(|()| manifest));
+        match result {
+            Ok(_) => log::info!("Archived {} files from job {} to `{}`", candidates.len(), job, path.display()),
+            Err(e) => {
+                log::warn!("Failed to archive job {}, keeping its files: {}", job, e);
+                errors.push(error::Error::new(&path, e));
+                continue;
+            },
+        }
+
+        // a file changed while it was archived has an older copy in the archive
+        let unchanged: Vec<Candidate> = candidates.into_iter()
+            .filter(|candidate| match candidate.changed() {
+                Some(path) => {
+                    log::warn!("Keeping `{}` (changed while it was archived)", path.display());
+                    false
+                },
+                None => true,
+            })
+            .cloned()
+            .collect();
+
+        archived += remove_files(&unchanged, Removal::Archived, journal, activity, throttle, report, errors);
+    }
+
+    archived
+}
+
+/// Where removed files go, from `--quarantine` and `--tier`
+struct Storage<'a> {
+    quarantine: Option<&'a Path>,
+    tier: Option<&'a Path>,
+    stub: bool,
+
+    /// how long quarantine runs are kept
+    grace: Duration,
+}
+
+/// Quarantine or cold storage set up for a run
+enum Destination {
+    Delete,
+    Quarantine(Quarantine),
+    Move(Tier),
+}
+
+impl Storage<'_> {
+    /// Set up the quarantine (purging expired runs first) or cold storage for a run
+    ///
+    /// Files are only ever deleted outright if neither was given.
+    fn open(&self, run: &str, roots: &[Root], journal: &Journal, errors: &mut Vec<error::Error>) -> Result<Destination, Box<dyn Error>> {
+        Ok(match (self.quarantine, self.tier) {
+            (Some(dir), _) => {
+                let purged = quarantine::purge(dir, self.grace, journal, errors)?;
+                log::info!("Purged {} expired quarantine runs", purged);
+
+                Destination::Quarantine(Quarantine::new(dir, run, roots)?)
+            },
+            (None, Some(dir)) => Destination::Move(Tier::new(dir, run, roots, self.stub)?),
+            (None, None) => Destination::Delete,
+        })
+    }
+}
+
+impl Destination {
+    fn removal(&self) -> Removal<'_> {
+        match self {
+            Self::Delete => Removal::Delete,
+            Self::Quarantine(quarantine) => Removal::Quarantine(quarantine),
+            Self::Move(tier) => Removal::Move(tier),
+        }
+    }
+}
+
+/// What is done with removed files
+#[derive(Clone, Copy)]
+enum Removal<'a> {
+    /// they are deleted
+    Delete,
+
+    /// they are moved into quarantine
+    Quarantine(&'a Quarantine),
+
+    /// they are moved to cold storage
+    Move(&'a Tier),
+
+    /// they are deleted, having been stored in an archive
+    Archived,
+}
+
+fn remove_files(files: &[Candidate], removal: Removal, journal: &Journal, activity: &Activity, throttle: &Throttle, report: &mut Report, errors: &mut Vec<error::Error>) -> u32 {
+    let mut removed = 0;
+    for candidate in files {
+        candidate.files().for_each(|_| throttle.wait());
+
+        // never pull a file out from under NX or Sigmanest
+        if let Some(busy) = activity.check(candidate, errors) {
+            log::warn!("Deferring `{}` (in use: {})", candidate.file.path.display(), busy);
+            report.defer(candidate);
+            continue;
+        }
+
+        match remove_file(candidate, removal, journal, errors) {
+            Ok(()) => {
+                report.add(candidate);
+                removed += 1;
+            },
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if report.deferred.files > 0 {
+        log::warn!("Deferred {} files that look to be in use to a later run", report.deferred.files);
+    }
+
+    removed
+}
+
+/// Remove a file and its companion files (i.e. a .dxf file and its .log
+/// file), moving them into quarantine or cold storage if given
+/// 
+/// The files are removed as a unit: either all are removed or none are.
+/// Each removed file is recorded in the audit journal; a file that
+/// cannot be recorded is still removed, but is added to `errors`.
+fn remove_file(candidate: &Candidate, removal: Removal, journal: &Journal, errors: &mut Vec<error::Error>) -> Result<(), error::Error> {
+    log::debug!("Removing {} file {}", candidate.rule, candidate.file.path.display());
+
+    // never remove a file when any of its companion files are missing
+    if let Some(missing) = candidate.companions.iter().find(|c| !c.path.is_file()) {
+        log::warn!("Keeping `{}` (missing `{}`)", candidate.file.path.display(), missing.path.display());
+        return Err(error::Error::new(&missing.path, io::Error::new(io::ErrorKind::NotFound, "missing companion file")));
+    }
+
+    // files must be hashed for the journal before they are gone
+    let hashes = candidate.files()
+        .map(|f| audit::sha256(&f.path).map_err(|e| error::Error::new(&f.path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let record = |action: Action, count: usize, errors: &mut Vec<error::Error>| {
+        for (file, sha256) in candidate.files().zip(&hashes).take(count) {
+            if let Err(e) = journal.record(action, &file.path, file, sha256.clone()) {
+                log::error!("Failed to record `{}` in the audit journal: {}", file.path.display(), e);
+                errors.push(error::Error::new(&file.path, e));
+            }
+        }
+    };
+
+    // puts files back after a failure, keeping the original error
+    let roll_back = |paths: &[&Path], undo: &dyn Fn(&Path) -> io::Result<()>| {
+        for path in paths {
+            if let Err(e) = undo(path) {
+                log::error!("Failed to roll back `{}`: {}", path.display(), e);
+            }
+        }
+    };
+
+    let paths: Vec<&Path> = candidate.files().map(|f| f.path.as_path()).collect();
+    let action = match removal {
+        Removal::Quarantine(quarantine) => {
+            for (i, path) in paths.iter().enumerate() {
+                if let Err(e) = quarantine.store(path) {
+                    log::warn!("Failed to quarantine `{}`, rolling back: {}", path.display(), e);
+                    roll_back(&paths[..i], &|stored| quarantine.unstore(stored));
+
+                    return Err(error::Error::new(path, e));
+                }
+            }
+
+            Action::Quarantine
+        },
+
+        Removal::Move(tier) => {
+            for (i, path) in paths.iter().enumerate() {
+                if let Err(e) = tier.store(path) {
+                    log::warn!("Failed to move `{}`, rolling back: {}", path.display(), e);
+                    roll_back(&paths[..i], &|moved| tier.unstore(moved));
+
+                    return Err(error::Error::new(path, e));
+                }
+            }
+            paths.iter().for_each(|path| tier.leave_stub(path));
+
+            Action::Move
+        },
+
+        Removal::Delete | Removal::Archived => {
+            let action = match removal {
+                Removal::Archived => Action::Archive,
+                _ => Action::Delete,
+            };
+
+            // rename all files before deleting any, so that a locked file
+            // stops the group from being removed while all can still be put back
+            for (i, path) in paths.iter().enumerate() {
+                if let Err(e) = stage(path) {
+                    log::warn!("Failed to remove `{}`, rolling back: {}", path.display(), e);
+                    roll_back(&paths[..i], &|renamed| fs::rename(staged(renamed), renamed));
+
+                    return Err(error::Error::new(path, e));
+                }
+            }
+
+            for (i, path) in paths.iter().enumerate() {
+                if let Err(e) = fs::remove_file(staged(path)) {
+                    log::warn!("Failed to remove `{}`: {}", path.display(), e);
+                    record(action, i, errors);
+
+                    // files that were not yet deleted can still be put back
+                    roll_back(&paths[i..], &|remaining| fs::rename(staged(remaining), remaining));
+
+                    return Err(error::Error::new(path, e));
+                }
+            }
+
+            action
+        },
+    };
+
+    record(action, paths.len(), errors);
+
+    Ok(())
+}
+
+/// Remove the folders of removed files that are now empty (i.e. `DXF`
+/// folders), and the folders above them that end up empty
+///
+/// Up to `depth` folders are removed going up from each folder. Only
+/// folders under a job's `Fab` folder are removed, never the `Fab`
+/// folder itself, the job folder or the root.
+fn remove_empty_dirs(files: &[Candidate], roots: &[Root], depth: usize, throttle: &Throttle, errors: &mut Vec<error::Error>) -> usize {
+    let below_fab = |dir: &Path| roots.iter()
+        .filter_map(|root| JobPath::parse(&root.path, dir).ok())
+        .any(|path| !path.fab.as_os_str().is_empty() || path.artifact.is_some());
+
+    // subfolders sort after their parents, so going backwards empties them first
+    let dirs: BTreeSet<&Path> = files.iter()
+        .flat_map(|candidate| candidate.files())
+        .filter_map(|file| file.path.parent())
+        .collect();
+
+    let mut removed = 0;
+    for dir in dirs.into_iter().rev() {
+        for dir in dir.ancestors().take(depth).take_while(|dir| below_fab(dir)) {
+            throttle.wait();
+            match fs::remove_dir(dir) {
+                Ok(()) => {
+                    log::info!("Removed empty folder `{}`", dir.display());
+                    removed += 1;
+                },
+                // already removed going up from another folder
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => break,
+                Err(e) => {
+                    errors.push(error::Error::new(dir, e));
+                    break;
+                },
+            }
+        }
+    }
+
+    removed
+}
+
+/// Temporary name for a file that is about to be deleted
+fn staged(path: &Path) -> PathBuf {
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(cleanup::STAGED_SUFFIX);
+
+    staged.into()
+}
+
+/// Rename a file to its temporary name, never replacing a file left
+/// behind by an interrupted removal
+fn stage(path: &Path) -> io::Result<()> {
+    let staged = staged(path);
+    match staged.symlink_metadata() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("`{}` already exists", staged.display()))),
+    }
+
+    fs::rename(path, staged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prodctrl::cleanup::Exclusions;
+    use prodctrl::guard::Guard;
+
+    /// A folder holding `part.dxf` and `part.log`, and a journal
+    fn setup(name: &str) -> (PathBuf, Candidate, Journal) {
+        let dir = std::env::temp_dir().join(format!("prodctrl_remove_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("jobs")).unwrap();
+
+        let [dxf, log] = ["part.dxf", "part.log"].map(|name| {
+            let path = dir.join("jobs").join(name);
+            fs::write(&path, name).unwrap();
+            FileInfo::read(&path).unwrap()
+        });
+        let candidate = Candidate { rule: String::from("dxf"), file: dxf, companions: vec![log], reason: String::new() };
+        let journal = Journal::open(&dir.join("audit.jsonl"), audit::Run::new("test", "test")).unwrap();
+
+        (dir, candidate, journal)
+    }
+
+    fn assert_untouched(dir: &Path, candidate: &Candidate) {
+        for file in candidate.files() {
+            assert!(file.path.is_file(), "`{}` was not rolled back", file.path.display());
+        }
+        assert_eq!(fs::read_to_string(dir.join("audit.jsonl")).unwrap(), "");
+    }
+
+    #[test]
+    fn delete_rolls_back_pair() {
+        let (dir, candidate, journal) = setup("delete");
+
+        // a leftover from an interrupted run stops the companion from being staged
+        fs::write(staged(&candidate.companions[0].path), "").unwrap();
+
+        let mut errors = Vec::new();
+        assert!(remove_file(&candidate, Removal::Delete, &journal, &mut errors).is_err());
+        assert!(errors.is_empty());
+        assert!(!staged(&candidate.file.path).exists());
+        assert_untouched(&dir, &candidate);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn quarantine_rolls_back_pair() {
+        let (dir, candidate, journal) = setup("quarantine");
+        let root = Root {
+            name: String::from("jobs"),
+            path: dir.join("jobs"),
+            rules: Vec::new(),
+            exclusions: Exclusions::new(BTreeSet::new(), &[]).unwrap(),
+            guard: Guard::default(),
+        };
+        let quarantine = Quarantine::new(&dir.join("quarantine"), "run", &[root]).unwrap();
+
+        // a folder in the way stops the companion from being quarantined
+        fs::create_dir_all(dir.join("quarantine/run/jobs/part.log/x")).unwrap();
+
+        let mut errors = Vec::new();
+        assert!(remove_file(&candidate, Removal::Quarantine(&quarantine), &journal, &mut errors).is_err());
+        assert!(!dir.join("quarantine/run/jobs/part.dxf").exists());
+        assert_untouched(&dir, &candidate);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removes_pair() {
+        let (dir, candidate, journal) = setup("removes");
+
+        let mut errors = Vec::new();
+        remove_file(&candidate, Removal::Delete, &journal, &mut errors).unwrap();
+        for file in candidate.files() {
+            assert!(!file.path.exists() && !staged(&file.path).exists());
+        }
+        assert_eq!(fs::read_to_string(dir.join("audit.jsonl")).unwrap().lines().count(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
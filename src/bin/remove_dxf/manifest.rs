@@ -0,0 +1,67 @@
+//! Deletion manifest
+//!
+//! A manifest lists every file (and its companion files) that a run
+//! selected for deletion. During a dry run, this is written to disk (as
+//! both CSV and JSON) instead of deleting anything, so that the list
+//! can be reviewed before a cleanup is done for real.
+
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use prodctrl::cleanup::Candidate;
+
+/// A row of the CSV manifest
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    /// rule that selected the file
+    rule: &'a str,
+
+    /// path to the selected file
+    file: &'a Path,
+
+    /// paths to the companion files (i.e. the `.log` file for a `.dxf` file)
+    companions: String,
+
+    /// size of the file and its companions, in bytes
+    size: u64,
+
+    /// last modified time of the file
+    modified: DateTime<Local>,
+
+    /// why the file qualified for deletion
+    reason: &'a str,
+}
+
+impl<'a> From<&'a Candidate> for Row<'a> {
+    fn from(candidate: &'a Candidate) -> Self {
+        Self {
+            rule: &candidate.rule,
+            file: &candidate.file.path,
+            companions: candidate.companions.iter()
+                .map(|c| c.path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(";"),
+            size: candidate.size(),
+            modified: candidate.file.modified,
+            reason: &candidate.reason,
+        }
+    }
+}
+
+/// Write the manifest as `<path>.csv` and `<path>.json`
+pub fn write(path: &Path, candidates: &[Candidate]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_path(path.with_extension("csv"))?;
+    for candidate in candidates {
+        wtr.serialize(Row::from(candidate))?;
+    }
+    wtr.flush()?;
+
+    let json = File::create(path.with_extension("json"))?;
+    serde_json::to_writer_pretty(json, candidates)?;
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Orphan report
+//!
+//! DXF files and `.log` files are expected to come in pairs. The report
+//! lists both ways a pair can be broken:
+//!
+//! - `.log` files without a `.dxf` file, which are left over from a DXF
+//!   file being deleted by hand and are only clutter, and
+//! - `.dxf` files without a `.log` file, which were made by hand (or
+//!   did not come from NX) and so are never removed.
+//!
+//! With `--clean`, orphaned `.log` files older than the rule's retention
+//! are removed the same way as any other file.
+
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+
+use prodctrl::cleanup::{Found, KeepReason};
+
+/// Which side of a pair is missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Kind {
+    /// a companion file without its file (i.e. a `.log` without a `.dxf`)
+    Orphan,
+
+    /// a file without its companion file (i.e. a `.dxf` without a `.log`)
+    Unpaired,
+}
+
+/// A row of the CSV report
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    /// which side of the pair is missing
+    kind: Kind,
+
+    /// path to the file that is there
+    file: &'a Path,
+
+    /// path to the file that is missing
+    missing: &'a Path,
+}
+
+/// Orphaned files found by a search
+pub struct Orphans<'a> {
+    rows: Vec<Row<'a>>,
+}
+
+impl<'a> Orphans<'a> {
+    /// Collect the orphaned files from a search
+    pub fn new(found: &'a Found) -> Self {
+        let companions = found.orphans.iter()
+            .map(|orphan| Row { kind: Kind::Orphan, file: &orphan.file.path, missing: &orphan.missing });
+        let files = found.kept.iter()
+            .filter_map(|kept| match &kept.reason {
+                KeepReason::MissingCompanion(missing) => Some(Row { kind: Kind::Unpaired, file: &kept.path, missing }),
+                _ => None,
+            });
+
+        Self { rows: companions.chain(files).collect() }
+    }
+
+    /// Number of files of a kind
+    fn count(&self, kind: Kind) -> usize {
+        self.rows.iter().filter(|row| row.kind == kind).count()
+    }
+
+    /// Write the report as a CSV file
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_path(path)?;
+        for row in &self.rows {
+            wtr.serialize(row)?;
+        }
+        wtr.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Console listing of the report
+impl fmt::Display for Orphans<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in &self.rows {
+            let kind = match row.kind {
+                Kind::Orphan => "orphan",
+                Kind::Unpaired => "unpaired",
+            };
+            writeln!(f, "{:<10} {} (no `{}`)", kind, row.file.display(), name(row.missing))?;
+        }
+
+        write!(f, "{} orphaned companion files, {} unpaired files", self.count(Kind::Orphan), self.count(Kind::Unpaired))
+    }
+}
+
+fn name(path: &Path) -> std::borrow::Cow<'_, str> {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy()
+}
@@ -0,0 +1,88 @@
+//! Two-phase plan/apply workflow
+//!
+//! `plan` records every file (and its companion files) that would be
+//! deleted, along with the size and last modified time seen for each.
+//! `apply` then deletes only the files in that plan, skipping any file
+//! that has changed since the plan was written. This lets the exact set of
+//! files be reviewed and signed off on before anything is deleted.
+//!
+//! A plan that goes over the configured limits is still written, with
+//! the limits it went over, but `apply` refuses it unless the limits are
+//! explicitly overridden (`--override-limits`).
+//!
+//! A plan is only applied if all of its roots and files are under the
+//! configured roots, which have just passed their safety checks.
+//! Protected jobs, excluded folders and `.keep` markers are checked
+//! again for every file, since they may have changed after the plan was
+//! made.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use prodctrl::breaker::Trip;
+use prodctrl::cleanup::Candidate;
+
+use crate::config::Root;
+
+/// A reviewed set of files to delete
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    /// when the plan was made
+    pub created: DateTime<Local>,
+
+    /// root directories that were searched
+    pub roots: Vec<PathBuf>,
+
+    /// files to delete
+    pub files: Vec<Candidate>,
+
+    /// limits the plan went over when it was made
+    #[serde(default)]
+    pub trips: Vec<String>,
+}
+
+impl Plan {
+    /// Create a plan from a list of files
+    pub fn new(roots: &[Root], files: Vec<Candidate>, trips: &[Trip]) -> Self {
+        let roots = roots.iter().map(|root| root.path.clone()).collect();
+        let trips = trips.iter().map(Trip::to_string).collect();
+
+        Self { created: Local::now(), roots, files, trips }
+    }
+
+    /// Check that the plan only removes files from the given roots
+    pub fn check_roots(&self, roots: &[Root]) -> Result<(), Box<dyn Error>> {
+        let guarded = |path: &Path| {
+            path.components().all(|c| c != Component::ParentDir)
+                && roots.iter().any(|root| path.starts_with(&root.path))
+        };
+
+        if let Some(root) = self.roots.iter().find(|root| !roots.iter().any(|r| r.path == **root)) {
+            return Err(format!("plan root `{}` is not a configured root", root.display()).into());
+        }
+        if let Some(file) = self.files.iter().flat_map(Candidate::files).find(|file| !guarded(&file.path)) {
+            return Err(format!("`{}` in the plan is not under a configured root", file.path.display()).into());
+        }
+
+        Ok(())
+    }
+
+    /// Read a plan from a JSON file
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(path)?);
+
+        Ok( serde_json::from_reader(reader)? )
+    }
+
+    /// Write the plan to a JSON file
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+//! Quarantine instead of deleting
+//!
+//! Rather than deleting files outright, they can be moved into a
+//! quarantine tree. Each run gets its own dated folder that keeps the
+//! original path layout (relative to the configured root, under a
+//! folder named for that root), so files can be
+//! put back with `restore` if Sigmanest still needs them. Runs older
+//! than the grace period are purged.
+//!
+//! A restored file is given a new modified time, so that it is not old
+//! enough to be removed again until another retention period has gone by.
+//! (Its original modified time is kept in the audit journal.)
+//!
+//! ```text
+//! <quarantine>\
+//!     2024-03-01_020000\
+//!         run.json
+//!         jobs\1210123\Fab\...\DXF\part.dxf
+//!         jobs\1210123\Fab\...\DXF\part.log
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use wax::{Glob, Pattern};
+
+use prodctrl::audit::{self, Action, Journal};
+use prodctrl::cleanup::FileInfo;
+use prodctrl::error;
+
+use crate::config::Root;
+
+const RUN_FILE: &str = "run.json";
+
+/// Information about a quarantine run, stored in `run.json`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Run {
+    /// id of the run (also the name of the run's folder)
+    pub id: String,
+
+    /// when the run was made
+    pub created: DateTime<Local>,
+
+    /// root directories files were moved from, by root name
+    pub roots: BTreeMap<String, PathBuf>,
+}
+
+/// Quarantine folder for a single run
+pub struct Quarantine {
+    dir: PathBuf,
+    roots: BTreeMap<String, PathBuf>,
+}
+
+impl Quarantine {
+    /// Create a new run folder in the quarantine directory
+    pub fn new(quarantine: &Path, id: &str, roots: &[Root]) -> Result<Self, Box<dyn Error>> {
+        let created = Local::now();
+        let id = id.to_string();
+        let dir = quarantine.join(&id);
+        let roots: BTreeMap<_, _> = roots.iter()
+            .map(|root| (root.name.clone(), root.path.clone()))
+            .collect();
+
+        fs::create_dir_all(&dir)?;
+        let run = Run { id, created, roots: roots.clone() };
+        serde_json::to_writer_pretty(File::create(dir.join(RUN_FILE))?, &run)?;
+
+        log::info!("Quarantining files to `{}`", dir.display());
+
+        Ok( Self { dir, roots } )
+    }
+
+    /// Move a file into quarantine, keeping its path relative to its root
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        move_file(path, &self.quarantined(path)?)
+    }
+
+    /// Move a file that was just quarantined back to where it came from
+    pub fn unstore(&self, path: &Path) -> io::Result<()> {
+        move_file(&self.quarantined(path)?, path)
+    }
+
+    /// Where a file is kept while in quarantine
+    fn quarantined(&self, path: &Path) -> io::Result<PathBuf> {
+        self.roots.iter()
+            .find_map(|(name, root)| {
+                path.strip_prefix(root).ok()
+                    .map(|relative| self.dir.join(name).join(relative))
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("`{}` is not under a configured root", path.display())))
+    }
+}
+
+/// Which quarantined files to restore
+#[derive(Debug, Default)]
+pub struct Selection {
+    /// only restore files from this run
+    pub run: Option<String>,
+
+    /// only restore files from this job
+    pub job: Option<String>,
+
+    /// only restore files whose path (relative to its root) matches this glob
+    pub glob: Option<String>,
+}
+
+/// Delete quarantine runs that are older than the grace period
+///
+/// Each file is recorded in the journal before it is deleted. A file
+/// that cannot be purged is added to `errors`, and its run is kept (with
+/// whatever files are left in it) to be purged by a later run.
+pub fn purge(quarantine: &Path, grace: Duration, journal: &Journal, errors: &mut Vec<error::Error>) -> Result<u32, Box<dyn Error>> {
+    let files = Glob::new("**/*")?;
+
+    let mut purged = 0;
+    for (dir, run) in runs(quarantine, errors)? {
+        if (Local::now() - run.created).to_std().unwrap_or_default() > grace {
+            log::info!("Purging quarantine run {}", run.id);
+
+            let failed = errors.len();
+            for entry in files.walk(&dir) {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        let path = e.path().unwrap_or(&dir).to_path_buf();
+                        errors.push(error::Error::new(&path, e.into()));
+                        continue;
+                    },
+                };
+                if !entry.file_type().is_file() || entry.path() == dir.join(RUN_FILE) {
+                    continue;
+                }
+
+                if let Err(e) = purge_file(entry.path(), journal) {
+                    errors.push(error::Error::new(entry.path(), e));
+                }
+            }
+
+            if errors.len() > failed {
+                log::warn!("Keeping quarantine run {} ({} files could not be purged)", run.id, errors.len() - failed);
+                continue;
+            }
+            if let Err(e) = fs::remove_dir_all(&dir) {
+                errors.push(error::Error::new(&dir, e));
+                continue;
+            }
+
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
+
+fn purge_file(path: &Path, journal: &Journal) -> io::Result<()> {
+    let (file, sha256) = (FileInfo::read(path)?, audit::sha256(path)?);
+    journal.record(Action::Delete, path, &file, sha256)?;
+
+    fs::remove_file(path)
+}
+
+/// Move quarantined files back to where they came from
+///
+/// Files that already exist at their original location are not
+/// overwritten. Restored files are touched, so the next run does not
+/// quarantine them again. Files that cannot be found or restored are
+/// added to `errors`.
+pub fn restore(quarantine: &Path, selection: &Selection, journal: &Journal, errors: &mut Vec<error::Error>) -> Result<u32, Box<dyn Error>> {
+    let glob = selection.glob.as_deref().map(Glob::new).transpose()?;
+    let files = Glob::new("**/*")?;
+
+    let mut restored = 0;
+    for (dir, run) in runs(quarantine, errors)? {
+        if selection.run.as_ref().is_some_and(|id| *id != run.id) {
+            continue;
+        }
+
+        for entry in files.walk(&dir) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let path = e.path().unwrap_or(&dir).to_path_buf();
+                    errors.push(error::Error::new(&path, e.into()));
+                    continue;
+                },
+            };
+            if !entry.file_type().is_file() || entry.path() == dir.join(RUN_FILE) {
+                continue;
+            }
+
+            // path is <run>\<root name>\<job>\...
+            let mut components = entry.path().strip_prefix(&dir)?.components();
+            let Some(root) = components.next().and_then(|name| run.roots.get(name.as_os_str().to_str()?)) else {
+                log::warn!("Not restoring `{}` (unknown root)", entry.path().display());
+                continue;
+            };
+            let relative = components.as_path();
+
+            let job = relative.components().next().map(|c| c.as_os_str());
+            if selection.job.as_ref().is_some_and(|j| job != Some(j.as_ref())) {
+                continue;
+            }
+            if glob.as_ref().is_some_and(|g| !g.is_match(relative)) {
+                continue;
+            }
+
+            let target = root.join(relative);
+            if target.exists() {
+                log::warn!("Not restoring `{}` (file already exists)", target.display());
+                continue;
+            }
+
+            log::debug!("Restoring `{}`", target.display());
+            match restore_file(entry.path(), &target, journal) {
+                Ok(()) => restored += 1,
+                Err(e) => errors.push(error::Error::new(&target, e)),
+            }
+        }
+    }
+
+    Ok(restored)
+}
+
+fn restore_file(path: &Path, target: &Path, journal: &Journal) -> io::Result<()> {
+    let (file, sha256) = (FileInfo::read(path)?, audit::sha256(path)?);
+    move_file(path, target)?;
+    File::options().write(true).open(target)?.set_modified(SystemTime::now())?;
+
+    journal.record(Action::Restore, target, &file, sha256)
+}
+
+/// All runs in the quarantine directory
+///
+/// A run that cannot be read is added to `errors` and left out.
+fn runs(quarantine: &Path, errors: &mut Vec<error::Error>) -> io::Result<Vec<(PathBuf, Run)>> {
+    let mut runs = Vec::new();
+    let entries = match fs::read_dir(quarantine) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(runs),
+        entries => entries?,
+    };
+
+    for entry in entries {
+        let dir = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                errors.push(error::Error::new(quarantine, e));
+                continue;
+            },
+        };
+
+        let file = match File::open(dir.join(RUN_FILE)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::debug!("Skipping `{}` (not a quarantine run)", dir.display());
+                continue;
+            },
+            Err(e) => {
+                errors.push(error::Error::new(&dir.join(RUN_FILE), e));
+                continue;
+            },
+        };
+
+        match serde_json::from_reader(BufReader::new(file)) {
+            Ok(run) => runs.push((dir, run)),
+            Err(e) => errors.push(error::Error::new(&dir.join(RUN_FILE), e.into())),
+        }
+    }
+
+    Ok(runs)
+}
+
+/// Move a file, falling back to copy and delete when a rename
+/// is not possible (i.e. across drives or shares)
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::rename(from, to).is_err() {
+        // a copy gets a new modified time, but the file's age goes by the old one
+        let modified = fs::metadata(from)?.modified()?;
+        fs::copy(from, to)?;
+        File::options().write(true).open(to)?.set_modified(modified)?;
+        fs::remove_file(from)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! Move to cold storage instead of deleting
+//!
+//! Rather than deleting files, they can be moved to a second, cheaper
+//! storage root. The tier mirrors the layout of the Jobs share (relative
+//! to the configured root, under a folder named for that root), so an
+//! old job's files are easy to find and copy back by hand. Unlike
+//! quarantine, files are kept there for good.
+//!
+//! ```text
+//! <tier>\
+//!     jobs\1210123\Fab\...\DXF\part.dxf
+//!     jobs\1210123\Fab\...\DXF\part.log
+//! ```
+//!
+//! With `--stub`, a small text file (i.e. `part.dxf.moved.txt`) is left
+//! where each file was, saying where it went.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use crate::config::Root;
+use crate::quarantine::move_file;
+
+/// Added to the name of a moved file for its stub
+pub const STUB_SUFFIX: &str = ".moved.txt";
+
+/// Cold-storage root that files are moved to
+pub struct Tier {
+    dir: PathBuf,
+    run: String,
+    roots: BTreeMap<String, PathBuf>,
+    stub: bool,
+}
+
+impl Tier {
+    /// Move files from the given roots into a cold-storage root
+    pub fn new(tier: &Path, run: &str, roots: &[Root], stub: bool) -> io::Result<Self> {
+        let roots = roots.iter()
+            .map(|root| (root.name.clone(), root.path.clone()))
+            .collect();
+
+        fs::create_dir_all(tier)?;
+        log::info!("Moving files to `{}`", tier.display());
+
+        Ok( Self { dir: tier.to_path_buf(), run: run.to_string(), roots, stub } )
+    }
+
+    /// Move a file into the tier, keeping its path relative to its root
+    ///
+    /// A file already in the tier at the same path is never overwritten.
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        let target = self.mirrored(path)?;
+        if target.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("`{}` already exists", target.display())));
+        }
+
+        move_file(path, &target)
+    }
+
+    /// Move a file that was just moved into the tier back to where it came from
+    pub fn unstore(&self, path: &Path) -> io::Result<()> {
+        move_file(&self.mirrored(path)?, path)
+    }
+
+    /// Leave a stub where a moved file was, if stubs are wanted
+    ///
+    /// The file has already been moved, so a stub that cannot be written
+    /// is only logged.
+    pub fn leave_stub(&self, path: &Path) {
+        if !self.stub {
+            return;
+        }
+
+        let Ok(target) = self.mirrored(path) else { return };
+        let text = format!(
+            "Moved to cold storage by run {} on {}:\r\n{}\r\n",
+            self.run, Local::now().format("%Y-%m-%d"), target.display()
+        );
+
+        let mut stub = path.as_os_str().to_owned();
+        stub.push(STUB_SUFFIX);
+
+        if let Err(e) = fs::write(&stub, text) {
+            log::warn!("Failed to leave a stub for `{}`: {}", path.display(), e);
+        }
+    }
+
+    /// Where a file is kept in the tier
+    fn mirrored(&self, path: &Path) -> io::Result<PathBuf> {
+        self.roots.iter()
+            .find_map(|(name, root)| {
+                path.strip_prefix(root).ok()
+                    .map(|relative| self.dir.join(name).join(relative))
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("`{}` is not under a configured root", path.display())))
+    }
+}
@@ -0,0 +1,302 @@
+//! Circuit breaker for cleanup runs
+//!
+//! If the files on the share are bulk re-touched or re-timestamped (i.e.
+//! by an NX admin), a single night could make thousands of files look
+//! old enough to remove. [`Limits`] stops a run before anything is
+//! removed when:
+//!
+//! - it would remove more than a number of files or bytes,
+//! - it would remove more than a percentage of a rule's files in a job, or
+//! - it found many more candidates than past runs did (see [`read_history`]).
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::cleanup::{Candidate, Found};
+use crate::paths::JobPath;
+
+/// Default history file, relative to the working directory
+pub const HISTORY_FILE: &str = "prodctrl_history.jsonl";
+
+/// Limits on what a run can remove
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// most files (including companion files) a run can remove
+    pub max_files: Option<u64>,
+
+    /// most bytes a run can remove
+    pub max_bytes: Option<u64>,
+
+    /// highest percentage of a rule's files in one job that a run can remove
+    pub max_job_percent: Option<f64>,
+
+    /// highest ratio of files to remove over the average of past runs
+    pub max_growth: Option<f64>,
+
+    /// only check `max_growth` when a run would remove at least this many files
+    pub min_growth_files: u64,
+
+    /// number of past runs to average for `max_growth`
+    pub history_runs: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_files: None,
+            max_bytes: None,
+            max_job_percent: None,
+            max_growth: Some(5.0),
+            min_growth_files: 100,
+            history_runs: 10,
+        }
+    }
+}
+
+/// A limit that a run went over
+#[derive(Debug, Clone)]
+pub enum Trip {
+    /// too many files
+    Files {
+        /// files the run would remove
+        files: u64,
+        /// the limit
+        max: u64,
+    },
+
+    /// too many bytes
+    Bytes {
+        /// bytes the run would remove
+        bytes: u64,
+        /// the limit
+        max: u64,
+    },
+
+    /// too much of a job
+    Job {
+        /// the job
+        job: String,
+        /// the rule
+        rule: String,
+        /// files in the job the run would remove
+        files: u64,
+        /// files in the job matched by the rule
+        matched: u64,
+        /// the limit
+        max: f64,
+    },
+
+    /// many more files than past runs
+    Growth {
+        /// files the run would remove
+        files: u64,
+        /// average files removed by past runs
+        average: f64,
+        /// the limit
+        max: f64,
+    },
+}
+
+impl fmt::Display for Trip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Files { files, max } => write!(f, "{} files to remove (limit is {})", files, max),
+            Self::Bytes { bytes, max } => write!(f, "{} bytes to remove (limit is {})", bytes, max),
+            Self::Job { job, rule, files, matched, max } => {
+                write!(f, "{} of the {} {} files in job {} to remove (limit is {}%)", files, matched, rule, job, max)
+            },
+            Self::Growth { files, average, max } => {
+                write!(f, "{} files to remove, against an average of {:.0} (limit is {}x)", files, average, max)
+            },
+        }
+    }
+}
+
+/// Files (including companion files) and bytes removed by a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// id of the run
+    pub run: String,
+
+    /// when the run finished
+    pub time: DateTime<Local>,
+
+    /// files removed
+    pub files: u64,
+
+    /// bytes removed
+    pub bytes: u64,
+}
+
+impl RunSummary {
+    /// Summary of a run that has just finished
+    pub fn new(run: &str, files: u64, bytes: u64) -> Self {
+        Self { run: run.to_string(), time: Local::now(), files, bytes }
+    }
+}
+
+/// Read the summaries of past runs, kept as a JSON lines file (none if
+/// there is no history yet)
+pub fn read_history(path: &Path) -> io::Result<Vec<RunSummary>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut runs = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        match serde_json::from_str(&line?) {
+            Ok(run) => runs.push(run),
+            Err(e) => log::warn!("Skipping line {} of `{}`: {}", i + 1, path.display(), e),
+        }
+    }
+
+    Ok(runs)
+}
+
+/// Add the summary of a run to the history
+pub fn append_history(path: &Path, run: &RunSummary) -> io::Result<()> {
+    let mut line = serde_json::to_string(run)?;
+    line.push('\n');
+
+    OpenOptions::new().create(true).append(true).open(path)?
+        .write_all(line.as_bytes())
+}
+
+impl Limits {
+    /// Check the files and bytes a run would remove
+    pub fn check_totals(&self, candidates: &[Candidate]) -> Vec<Trip> {
+        let (files, bytes) = totals(candidates);
+
+        let mut trips = Vec::new();
+        if let Some(max) = self.max_files.filter(|max| files > *max) {
+            trips.push(Trip::Files { files, max });
+        }
+        if let Some(max) = self.max_bytes.filter(|max| bytes > *max) {
+            trips.push(Trip::Bytes { bytes, max });
+        }
+
+        trips
+    }
+
+    /// Check the results of a search against all of the limits
+    pub fn check(&self, found: &Found, roots: &[PathBuf], history: &[RunSummary]) -> Vec<Trip> {
+        let mut trips = self.check_totals(&found.candidates);
+
+        if let Some(max) = self.max_job_percent {
+            let job = |path: &Path| roots.iter()
+                .find_map(|root| JobPath::parse(root, path).ok())
+                .map(|path| path.job);
+
+            let mut matched: BTreeMap<(String, String), u64> = BTreeMap::new();
+            for ((rule, folder), count) in &found.matched {
+                if let Some(job) = job(folder) {
+                    *matched.entry((job, rule.clone())).or_default() += count;
+                }
+            }
+
+            let mut removed: BTreeMap<(String, String), u64> = BTreeMap::new();
+            for candidate in &found.candidates {
+                if let Some(job) = job(&candidate.file.path) {
+                    *removed.entry((job, candidate.rule.clone())).or_default() += 1;
+                }
+            }
+
+            for ((job, rule), files) in removed {
+                let matched = matched.get(&(job.clone(), rule.clone())).copied().unwrap_or(files);
+                if files as f64 > max / 100.0 * matched as f64 {
+                    trips.push(Trip::Job { job, rule, files, matched, max });
+                }
+            }
+        }
+
+        let (files, _) = totals(&found.candidates);
+        let recent = &history[history.len().saturating_sub(self.history_runs)..];
+        if let (Some(max), false) = (self.max_growth, recent.is_empty()) {
+            let average = recent.iter().map(|run| run.files as f64).sum::<f64>() / recent.len() as f64;
+            if files >= self.min_growth_files && files as f64 > max * average.max(1.0) {
+                trips.push(Trip::Growth { files, average, max });
+            }
+        }
+
+        trips
+    }
+}
+
+fn totals(candidates: &[Candidate]) -> (u64, u64) {
+    candidates.iter()
+        .map(|c| (1 + c.companions.len() as u64, c.size()))
+        .fold((0, 0), |(files, bytes), (f, b)| (files + f, bytes + b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cleanup::FileInfo;
+
+    const ROOT: &str = "/jobs";
+
+    fn candidate(path: &str) -> Candidate {
+        let file = FileInfo { path: PathBuf::from(path), size: 10, modified: Local::now() };
+
+        Candidate { rule: String::from("dxf"), file, companions: Vec::new(), reason: String::new() }
+    }
+
+    /// `count` candidates in a job's DXF folder, out of `matched` files
+    fn found(job: &str, count: usize, matched: u64) -> Found {
+        let folder = PathBuf::from(format!("{}/{}/Fab/Plates/DXF", ROOT, job));
+
+        Found {
+            candidates: (0..count).map(|i| candidate(&format!("{}/part{}.dxf", folder.display(), i))).collect(),
+            matched: BTreeMap::from([((String::from("dxf"), folder), matched)]),
+            ..Found::default()
+        }
+    }
+
+    fn history(files: &[u64]) -> Vec<RunSummary> {
+        files.iter().map(|files| RunSummary::new("run", *files, 0)).collect()
+    }
+
+    #[test]
+    fn job_percent() {
+        let limits = Limits { max_job_percent: Some(50.0), max_growth: None, ..Limits::default() };
+        let roots = [PathBuf::from(ROOT)];
+
+        assert!(limits.check(&found("1210123", 5, 10), &roots, &[]).is_empty());
+
+        let trips = limits.check(&found("1210123", 6, 10), &roots, &[]);
+        assert!(matches!(trips.as_slice(), [Trip::Job { job, files: 6, matched: 10, .. }] if job == "1210123"));
+    }
+
+    #[test]
+    fn growth() {
+        let limits = Limits { min_growth_files: 10, ..Limits::default() };
+        let roots = [PathBuf::from(ROOT)];
+        let found = found("1210123", 60, 60);
+
+        assert!(limits.check(&found, &roots, &history(&[20, 10])).is_empty());
+        assert!(matches!(limits.check(&found, &roots, &history(&[12, 10, 8])).as_slice(), [Trip::Growth { files: 60, .. }]));
+
+        // no trip without history, or below the minimum number of files
+        assert!(limits.check(&found, &roots, &[]).is_empty());
+        assert!(Limits { min_growth_files: 100, ..limits.clone() }.check(&found, &roots, &history(&[1])).is_empty());
+    }
+
+    #[test]
+    fn growth_uses_recent_runs() {
+        let limits = Limits { min_growth_files: 10, history_runs: 2, ..Limits::default() };
+        let found = found("1210123", 60, 60);
+
+        // only the last two runs (average 10) count
+        let trips = limits.check(&found, &[PathBuf::from(ROOT)], &history(&[100, 100, 10, 10]));
+        assert!(matches!(trips.as_slice(), [Trip::Growth { average, .. }] if *average == 10.0));
+    }
+}
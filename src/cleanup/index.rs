@@ -0,0 +1,117 @@
+//! Directory index for incremental searches
+//!
+//! Most of the Jobs share is untouched from one night to the next, so
+//! listing every folder again is wasted time. The index keeps, for
+//! each folder that was listed, its modified time and what was in it.
+//! A folder whose modified time has not changed is not listed again;
+//! its entries (and the size and modified time of its files, for
+//! folders matched by a rule) are taken from the index.
+//!
+//! A folder's modified time only changes when entries are added,
+//! removed or renamed in it, not when a file in it is changed in place.
+//! Files from the index that are old enough to be candidates are always
+//! checked again before they are used, and if any have changed the
+//! folder is listed again on the next run.
+//!
+//! The index is a JSON file, which is written whole after each search.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+/// Folders listed in previous searches
+#[derive(Debug, Default)]
+pub struct Index {
+    old: BTreeMap<PathBuf, Dir>,
+    new: Mutex<BTreeMap<PathBuf, Dir>>,
+    reused: AtomicUsize,
+}
+
+/// A folder, as it was when it was listed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct Dir {
+    /// modified time of the folder when it was listed
+    pub modified: DateTime<Local>,
+
+    /// subfolders
+    pub dirs: Vec<String>,
+
+    /// files, if the folder was matched by a rule
+    pub files: Option<Vec<IndexedFile>>,
+
+    /// whether the folder holds a `.keep` marker
+    pub keep: bool,
+
+    /// whether the folder holds an overrides file
+    pub overrides: bool,
+}
+
+/// A file in an indexed folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct IndexedFile {
+    pub name: String,
+    pub size: u64,
+    pub modified: DateTime<Local>,
+}
+
+impl Index {
+    /// Read an index file (an empty index is used if there is none)
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let old = match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::info!("No index at `{}`, listing all folders", path.display());
+                BTreeMap::new()
+            },
+            Err(e) => return Err(e),
+        };
+
+        Ok( Self { old, ..Default::default() } )
+    }
+
+    /// Write the folders listed (or reused) in this search
+    ///
+    /// Folders that were not reached (i.e. they were excluded or have
+    /// been removed) are dropped from the index.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let new = self.new.lock().unwrap_or_else(|e| e.into_inner());
+        log::info!("Indexed {} folders ({} not listed again)", new.len(), self.reused.load(Ordering::Relaxed));
+
+        // write next to the index and then replace it, so a failed write does not lose it
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer(&mut writer, &*new)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        fs::rename(&temp, path)
+    }
+
+    /// Look up a folder, if it has not changed since it was indexed
+    pub(super) fn get(&self, path: &Path, modified: DateTime<Local>, needs_files: bool) -> Option<Dir> {
+        let dir = self.old.get(path)
+            .filter(|dir| dir.modified == modified)
+            .filter(|dir| !needs_files || dir.files.is_some())?;
+
+        self.reused.fetch_add(1, Ordering::Relaxed);
+        Some(dir.clone())
+    }
+
+    /// Record a folder for the next search
+    pub(super) fn insert(&self, path: PathBuf, dir: Dir) {
+        self.new.lock().unwrap_or_else(|e| e.into_inner()).insert(path, dir);
+    }
+
+    /// Drop a folder, so that it is listed again on the next search
+    pub(super) fn forget(&self, path: &Path) {
+        self.new.lock().unwrap_or_else(|e| e.into_inner()).remove(path);
+    }
+}
@@ -0,0 +1,423 @@
+//! Cleanup of old files on the Jobs share
+//!
+//! What gets cleaned up is described by a [`CleanupRule`]: which folders
+//! to look in, which files in those folders are candidates, which
+//! companion files must go along with them and how old they must be.
+//! A rule is paired with its (possibly configured) [`Settings`] in a
+//! [`Rule`], which can then be used to [`search`] a root directory.
+//!
+//! Whole subtrees can be left out of a search with [`Exclusions`]:
+//! protected jobs, excluded folders and folders holding a `.keep`
+//! marker file are never walked. Rule settings can be changed for a
+//! subtree with a `.prodctrl.toml` file (see [`overrides`]).
+//!
+//! A root is searched in a single pass for all of its rules, pruning
+//! subtrees that no rule can match under as early as possible. Job
+//! folders are searched by a number of workers in parallel, and the
+//! filesystem operations of a search can be held to a rate with a
+//! [`Throttle`]. Folders that have not changed since the last search
+//! can be taken from an [`Index`] instead of being listed again.
+//!
+//! Companion files whose file is missing (i.e. a `.log` file whose
+//! `.dxf` file was deleted by hand) are collected as [`Orphan`]s.
+//!
+//! The built-in rules are in [`rules`].
+
+pub mod index;
+pub mod overrides;
+pub mod rules;
+mod scan;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use wax::{BuildError, Glob, Pattern};
+
+use crate::error::Error;
+use crate::jobs::JobNumber;
+use crate::throttle::Throttle;
+use index::Index;
+use scan::{FolderPattern, Scanner};
+
+/// Marker file that keeps the folder it is in (and everything under it)
+pub const KEEP_MARKER: &str = ".keep";
+
+/// Added to the name of a file while it is being deleted
+///
+/// A file is renamed before it is deleted, so a file with this suffix
+/// was left behind by a removal that was interrupted. Any found by a
+/// search are reported as errors.
+pub const STAGED_SUFFIX: &str = ".deleting";
+
+/// A policy for cleaning up a kind of file
+pub trait CleanupRule: Send + Sync {
+    /// Name of the rule, as used in the configuration file
+    fn name(&self) -> &'static str;
+
+    /// Default folders, files and retention for the rule
+    fn defaults(&self) -> Settings;
+
+    /// Companion files that must be removed along with a file
+    ///
+    /// A file is only removed if all of its companion files exist.
+    fn companions(&self, _file: &Path) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// File that a companion file goes along with, if it is one of this
+    /// rule's companion files (i.e. the `.dxf` file for a `.log` file)
+    ///
+    /// A companion file whose file does not exist is an [`Orphan`].
+    fn companion_of(&self, _companion: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    /// Check for a rule-specific reason to keep a file that is otherwise
+    /// eligible for cleanup
+    ///
+    /// `companions` are the companion files found for it, which may not
+    /// be the rule's own if they were overridden for its folder, and
+    /// `folder` holds the names of all the files in its folder (from the
+    /// listing the search already made). A file that cannot be checked is
+    /// an error, and is not removed.
+    fn keep(&self, _file: &Path, _companions: &[FileInfo], _folder: &[&OsStr]) -> io::Result<Option<KeepReason>> {
+        Ok(None)
+    }
+}
+
+/// Where a rule looks and how long files are kept
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// glob (relative to a root) matching folders to search
+    pub folders: String,
+
+    /// glob (relative to a matched folder) matching candidate files
+    pub files: String,
+
+    /// how long files are kept before they are eligible for cleanup
+    pub retention: Duration,
+}
+
+/// A cleanup rule and its settings
+pub struct Rule {
+    rule: Box<dyn CleanupRule>,
+    retention: Duration,
+    folders: FolderPattern,
+    files: Glob<'static>,
+}
+
+impl Rule {
+    /// Pair a rule with its settings
+    pub fn new(rule: Box<dyn CleanupRule>, settings: Settings) -> Result<Self, BuildError> {
+        Ok(Self {
+            rule,
+            retention: settings.retention,
+            folders: FolderPattern::new(&settings.folders)?,
+            files: Glob::new(&settings.files)?.into_owned(),
+        })
+    }
+
+    /// Name of the rule
+    pub fn name(&self) -> &'static str {
+        self.rule.name()
+    }
+
+    /// How long files are kept
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+}
+
+/// Subtrees of a root that are never searched
+#[derive(Debug, Default)]
+pub struct Exclusions {
+    jobs: BTreeSet<JobNumber>,
+    globs: Vec<Glob<'static>>,
+}
+
+impl Exclusions {
+    /// Exclude protected jobs and folders matching globs (relative to a root)
+    ///
+    /// Protecting a job also protects all of its structures.
+    pub fn new(jobs: BTreeSet<JobNumber>, globs: &[String]) -> Result<Self, BuildError> {
+        let globs = globs.iter()
+            .map(|glob| Glob::new(glob).map(Glob::into_owned))
+            .collect::<Result<_, _>>()?;
+
+        Ok( Self { jobs, globs } )
+    }
+
+    /// Check why a file under `root` must not be removed
+    ///
+    /// This makes the same checks a search makes on the way down to the
+    /// file: a protected job, an excluded folder or a `.keep` marker in
+    /// any folder from the root to the file. A marker that cannot be
+    /// checked for is an error, rather than no marker.
+    pub fn check(&self, root: &Path, path: &Path) -> io::Result<Option<String>> {
+        let relative = path.strip_prefix(root)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("`{}` is not under `{}`", path.display(), root.display())))?;
+
+        let mut folder = PathBuf::new();
+        for name in relative.parent().into_iter().flat_map(Path::components) {
+            folder.push(name);
+            if let Some(reason) = self.excluded(&folder) {
+                return Ok(Some(reason));
+            }
+        }
+
+        for dir in path.ancestors().skip(1).take_while(|dir| dir.starts_with(root)) {
+            if dir.join(KEEP_MARKER).try_exists()? {
+                return Ok(Some(format!("`{}` marker in `{}`", KEEP_MARKER, dir.display())));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Check why a folder (relative to a root) should not be searched
+    fn excluded(&self, relative: &Path) -> Option<String> {
+        // the job folder is the first one under the root
+        let mut components = relative.components();
+        if let (Some(job), None) = (components.next(), components.next()) {
+            let job = job.as_os_str().to_string_lossy().parse::<JobNumber>().ok();
+            if let Some(job) = job.filter(|job| self.jobs.contains(job) || self.jobs.contains(&job.job())) {
+                return Some(format!("protected job {}", job));
+            }
+        }
+
+        if let Some(glob) = self.globs.iter().find(|glob| glob.is_match(relative)) {
+            return Some(format!("excluded by `{}`", glob));
+        }
+
+        None
+    }
+}
+
+/// Which modified time is used for the age of a file and its companions
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AgeFrom {
+    /// modified time of the file
+    File,
+
+    /// modified time of the newest companion file
+    Companion,
+
+    /// newest modified time of the file and its companions
+    #[default]
+    Newest,
+}
+
+/// A file and the size and modified time seen for it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileInfo {
+    /// path to the file
+    pub path: PathBuf,
+
+    /// size of the file, in bytes
+    pub size: u64,
+
+    /// last modified time of the file
+    pub modified: DateTime<Local>,
+}
+
+impl FileInfo {
+    /// Read the size and modified time of a file
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::from_metadata(path, &fs::metadata(path)?)
+    }
+
+    fn from_metadata(path: &Path, metadata: &fs::Metadata) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified()?.into(),
+        })
+    }
+
+    /// Check if the file has changed (or is gone) since it was read
+    pub fn changed(&self) -> bool {
+        match Self::read(&self.path) {
+            Ok(now) => now.size != self.size || now.modified != self.modified,
+            Err(_) => true,
+        }
+    }
+}
+
+/// A file selected for cleanup, along with its companion files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    /// name of the rule that selected the file
+    pub rule: String,
+
+    /// the selected file
+    pub file: FileInfo,
+
+    /// companion files to remove along with the file
+    pub companions: Vec<FileInfo>,
+
+    /// why the file qualified for cleanup
+    pub reason: String,
+}
+
+impl Candidate {
+    /// The file and its companion files
+    pub fn files(&self) -> impl Iterator<Item = &FileInfo> {
+        std::iter::once(&self.file).chain(&self.companions)
+    }
+
+    /// Total size of the file and its companion files, in bytes
+    pub fn size(&self) -> u64 {
+        self.files().map(|f| f.size).sum()
+    }
+
+    /// Find the first file that has changed since the candidate was found
+    pub fn changed(&self) -> Option<&Path> {
+        self.files()
+            .find(|f| f.changed())
+            .map(|f| f.path.as_path())
+    }
+}
+
+/// A file that was kept even though it matched a rule
+#[derive(Debug, Clone)]
+pub struct Kept {
+    /// the kept file
+    pub path: PathBuf,
+
+    /// why the file was kept
+    pub reason: KeepReason,
+}
+
+/// Why a file was kept
+#[derive(Debug, Clone)]
+pub enum KeepReason {
+    /// a companion file does not exist
+    MissingCompanion(PathBuf),
+
+    /// the rule has its own reason to keep the file
+    Rule(String),
+
+    /// the rule found something wrong with the file that needs attention
+    Flagged(String),
+}
+
+impl fmt::Display for KeepReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingCompanion(path) => write!(f, "missing companion file `{}`", path.display()),
+            Self::Rule(reason) | Self::Flagged(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// A companion file whose file does not exist (i.e. a `.log` file left
+/// behind after its `.dxf` file was deleted by hand)
+#[derive(Debug, Clone)]
+pub struct Orphan {
+    /// name of the rule the companion file belongs to
+    pub rule: String,
+
+    /// the companion file
+    pub file: FileInfo,
+
+    /// the file it would go along with
+    pub missing: PathBuf,
+
+    /// whether the companion file is older than the rule's retention
+    pub expired: bool,
+}
+
+impl Orphan {
+    /// The orphan as a file to clean up
+    pub fn to_candidate(&self) -> Candidate {
+        Candidate {
+            rule: self.rule.clone(),
+            file: self.file.clone(),
+            companions: Vec::new(),
+            reason: format!("orphaned (no `{}`)", name(&self.missing)),
+        }
+    }
+}
+
+/// Results of searching a root
+#[derive(Debug, Default)]
+pub struct Found {
+    /// files eligible for cleanup
+    pub candidates: Vec<Candidate>,
+
+    /// files kept even though they matched a rule
+    pub kept: Vec<Kept>,
+
+    /// companion files without the file they go along with
+    pub orphans: Vec<Orphan>,
+
+    /// number of files matched by each rule in each folder, whether
+    /// they are eligible for cleanup or not
+    pub matched: BTreeMap<(String, PathBuf), u64>,
+
+    /// errors while searching
+    pub errors: Vec<Error>,
+}
+
+impl Found {
+    /// Add the results of another search
+    pub fn extend(&mut self, other: Found) {
+        self.candidates.extend(other.candidates);
+        self.kept.extend(other.kept);
+        self.orphans.extend(other.orphans);
+        for (key, count) in other.matched {
+            *self.matched.entry(key).or_default() += count;
+        }
+        self.errors.extend(other.errors);
+    }
+}
+
+/// Search a root directory for files eligible for cleanup under its rules
+///
+/// The job folders in the root are searched by `workers` threads. If an
+/// index is given, it is used for unchanged folders and updated.
+pub fn search(root: &Path, rules: &[Rule], age_from: AgeFrom, exclusions: &Exclusions, workers: usize, throttle: &Throttle, index: Option<&Index>) -> Found {
+    let mut found = Found::default();
+    Scanner { root, rules, age_from, exclusions, workers, throttle, index }.scan(&mut found);
+
+    found
+}
+
+fn name(path: &Path) -> std::borrow::Cow<'_, str> {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy()
+}
+
+/// Number of whole days in a [`Duration`]
+pub fn days(duration: Duration) -> u64 {
+    duration.as_secs() / (24 * 60 * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusions() {
+        let jobs = BTreeSet::from(["1210124".parse().unwrap()]);
+        let exclusions = Exclusions::new(jobs, &[String::from("1190*"), String::from("*/Fab/Keep")]).unwrap();
+        let root = Path::new("/no/such/root");
+        let check = |path: &str| exclusions.check(root, &root.join(path)).unwrap();
+
+        assert_eq!(check("1210124/Fab/DXF/a.dxf").as_deref(), Some("protected job 1210124"));
+        assert_eq!(check("1210124B/Fab/DXF/a.dxf").as_deref(), Some("protected job 1210124B"));
+        assert_eq!(check("1190042/Fab/DXF/a.dxf").as_deref(), Some("excluded by `1190*`"));
+        assert_eq!(check("1210123/Fab/Keep/DXF/a.dxf").as_deref(), Some("excluded by `*/Fab/Keep`"));
+        assert_eq!(check("1210123/Fab/DXF/a.dxf"), None);
+
+        // a file named like a protected job is not a job folder
+        assert_eq!(check("1210124"), None);
+        assert!(exclusions.check(root, Path::new("/elsewhere/a.dxf")).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! Per-folder overrides of rule settings
+//!
+//! Any folder (usually a job or `Fab` folder) can hold a
+//! `.prodctrl.toml` file that changes how rules behave for everything
+//! under it. Files further down the tree win over files further up, and
+//! a rule's own table wins over the settings for all rules:
+//!
+//! ```toml
+//! # keep everything in this job for a year
+//! retention_days = 365
+//!
+//! # ...except temporary revision DXFs, which can go after a week
+//! [rules.dxf]
+//! retention_days = 7
+//! files = "*_tmp.dxf"
+//! companions = []
+//! ```
+//!
+//! `companions` lists the extensions of the companion files that go
+//! along with a file (i.e. `["log"]`), replacing the rule's own.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use wax::Glob;
+
+use super::Rule;
+
+/// Name of the overrides file
+pub const OVERRIDES_FILE: &str = ".prodctrl.toml";
+
+/// Contents of an overrides file
+#[derive(Debug, Default, Clone)]
+pub struct Overrides {
+    /// days to keep files, for all rules
+    pub retention_days: Option<u64>,
+
+    /// changes to each rule's settings
+    pub rules: BTreeMap<String, RuleOverrides>,
+}
+
+/// Changes to a rule's settings
+#[derive(Debug, Default, Clone)]
+pub struct RuleOverrides {
+    /// days to keep files
+    pub retention_days: Option<u64>,
+
+    /// glob (relative to a matched folder) matching candidate files
+    pub files: Option<Glob<'static>>,
+
+    /// extensions of companion files
+    pub companions: Option<Vec<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OverridesFile {
+    retention_days: Option<u64>,
+    rules: BTreeMap<String, RuleOverridesFile>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RuleOverridesFile {
+    retention_days: Option<u64>,
+    files: Option<String>,
+    companions: Option<Vec<String>>,
+}
+
+impl Overrides {
+    /// Read the overrides file in a folder, if there is one
+    pub fn read(dir: &Path) -> io::Result<Option<Self>> {
+        let text = match fs::read_to_string(dir.join(OVERRIDES_FILE)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let file: OverridesFile = toml::from_str(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut rules = BTreeMap::new();
+        for (name, rule) in file.rules {
+            let files = match rule.files {
+                Some(files) => Some(Glob::new(&files)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+                    .into_owned()),
+                None => None,
+            };
+
+            rules.insert(name, RuleOverrides { retention_days: rule.retention_days, files, companions: rule.companions });
+        }
+
+        Ok( Some(Self { retention_days: file.retention_days, rules }) )
+    }
+}
+
+/// A rule's settings with the overrides for a folder applied
+#[derive(Clone)]
+pub(super) struct Applied {
+    pub retention: Duration,
+    pub files: Glob<'static>,
+    pub companions: Option<Vec<String>>,
+}
+
+impl Applied {
+    /// Apply overrides, from the outermost folder in
+    pub fn new<'o>(rule: &Rule, overrides: impl Iterator<Item = &'o Overrides>) -> Self {
+        let mut applied = Self { retention: rule.retention, files: rule.files.clone(), companions: None };
+
+        for overrides in overrides {
+            let rule_overrides = overrides.rules.get(rule.name());
+
+            if let Some(days) = rule_overrides.and_then(|r| r.retention_days).or(overrides.retention_days) {
+                applied.retention = Duration::from_secs(days * 24 * 60 * 60);    // days * hours * minutes * seconds
+            }
+            if let Some(files) = rule_overrides.and_then(|r| r.files.as_ref()) {
+                applied.files = files.clone();
+            }
+            if let Some(companions) = rule_overrides.and_then(|r| r.companions.as_ref()) {
+                applied.companions = Some(companions.clone());
+            }
+        }
+
+        applied
+    }
+
+    /// Whether the file glob can match files in subfolders
+    pub fn is_deep(&self) -> bool {
+        let files = self.files.to_string();
+
+        files.contains('/') || files.contains("**")
+    }
+
+    /// Companion files of a file
+    pub fn companions(&self, rule: &Rule, file: &Path) -> Vec<PathBuf> {
+        match &self.companions {
+            Some(extensions) => extensions.iter().map(|ext| file.with_extension(ext)).collect(),
+            None => rule.rule.companions(file),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Built-in cleanup rules
+
+use std::cmp::Ordering;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::nxlog::ExportLog;
+use super::{CleanupRule, FileInfo, KeepReason, Settings};
+
+const DAY: u64 = 24 * 60 * 60;  // hours * minutes * seconds
+
+/// Find a built-in rule by name
+pub fn by_name(name: &str) -> Option<Box<dyn CleanupRule>> {
+    match name {
+        "dxf" => Some(Box::new(Dxf)),
+        "nc" => Some(Box::new(Nc)),
+        "pdf" => Some(Box::new(SupersededPdf)),
+        "step" => Some(Box::new(Step)),
+        _ => None,
+    }
+}
+
+/// Names of all built-in rules
+pub const NAMES: [&str; 4] = ["dxf", "nc", "pdf", "step"];
+
+/// DXF files exported from NX for Sigmanest
+///
+/// DXF files are used as an intermediate format between NX and Sigmanest
+/// so that we do not version lock the two softwares. Only DXF files
+/// that have an associated `.log` file are removed (generally, only the
+/// NX generated DXF's will have one), and the `.log` file goes with it.
+///
+/// The `.log` file must also show a clean export (see [`ExportLog`]).
+/// DXF files from a failed or partial export are kept and flagged.
+pub struct Dxf;
+
+impl CleanupRule for Dxf {
+    fn name(&self) -> &'static str { "dxf" }
+
+    fn defaults(&self) -> Settings {
+        Settings {
+            folders: String::from("**/Fab/**/DXF"),
+            files: String::from("(?i)*.dxf"),
+            retention: Duration::from_secs(60 * DAY),
+        }
+    }
+
+    fn companions(&self, file: &Path) -> Vec<PathBuf> {
+        vec![file.with_extension("log")]
+    }
+
+    fn companion_of(&self, companion: &Path) -> Option<PathBuf> {
+        companion.extension()
+            .filter(|ext| ext.eq_ignore_ascii_case("log"))
+            .map(|_| companion.with_extension("dxf"))
+    }
+
+    fn keep(&self, _file: &Path, companions: &[FileInfo], _folder: &[&OsStr]) -> io::Result<Option<KeepReason>> {
+        // without its `.log` file (i.e. overridden to have no companions), there is no export to check
+        let Some(log) = companions.iter().find(|c| c.path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("log"))) else {
+            return Ok(None);
+        };
+
+        Ok(match ExportLog::read(&log.path) {
+            Ok(log) if log.is_clean() => None,
+            Ok(log) => Some(KeepReason::Flagged(log.to_string())),
+            Err(e) => Some(KeepReason::Flagged(format!("could not read .log file: {}", e))),
+        })
+    }
+}
+
+/// NC program outputs from Sigmanest
+pub struct Nc;
+
+impl CleanupRule for Nc {
+    fn name(&self) -> &'static str { "nc" }
+
+    fn defaults(&self) -> Settings {
+        Settings {
+            folders: String::from("**/Fab/**/NC"),
+            files: String::from("(?i)*.nc"),
+            retention: Duration::from_secs(180 * DAY),
+        }
+    }
+}
+
+/// Drawing PDFs that have been superseded by a later revision
+///
+/// Revisions are read from the end of the file name, such as
+/// `1210123A-1_Rev2.pdf` or `1210123A-1 rev B.pdf`. A PDF is only
+/// removed if the same folder has the same drawing at a later revision.
+pub struct SupersededPdf;
+
+impl CleanupRule for SupersededPdf {
+    fn name(&self) -> &'static str { "pdf" }
+
+    fn defaults(&self) -> Settings {
+        Settings {
+            folders: String::from("**/Fab/**/Drawings"),
+            files: String::from("(?i)*.pdf"),
+            retention: Duration::from_secs(60 * DAY),
+        }
+    }
+
+    fn keep(&self, file: &Path, _companions: &[FileInfo], folder: &[&OsStr]) -> io::Result<Option<KeepReason>> {
+        let Some((drawing, rev)) = file.file_stem().and_then(|s| s.to_str()).and_then(revision) else {
+            return Ok(Some(KeepReason::Rule(String::from("no revision in file name"))));
+        };
+
+        let superseded = folder.iter()
+            .map(Path::new)
+            .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pdf")))
+            .filter_map(|path| path.file_stem().and_then(|s| s.to_str()).and_then(revision))
+            .any(|(d, r)| d.eq_ignore_ascii_case(drawing) && compare_revisions(r, rev) == Ordering::Greater);
+
+        Ok(match superseded {
+            true => None,
+            false => Some(KeepReason::Rule(String::from("latest revision"))),
+        })
+    }
+}
+
+/// Split a file stem into its drawing and revision
+/// (i.e. `1210123A-1_Rev2` -> (`1210123A-1`, `2`))
+fn revision(stem: &str) -> Option<(&str, &str)> {
+    let at = stem.to_ascii_lowercase().rfind("rev")?;
+
+    // revision must be separated from the drawing (i.e. `_Rev2` or ` rev B`)
+    let drawing = stem[..at].strip_suffix(['_', '-', ' '])?;
+    let rev = stem[at + 3..].trim_start_matches(['_', '-', ' ', '.']);
+
+    match drawing.is_empty() || rev.is_empty() {
+        true => None,
+        false => Some((drawing, rev)),
+    }
+}
+
+/// Compare revisions, numerically if both are numbers
+fn compare_revisions(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u32>(), b.parse::<u32>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.len().cmp(&b.len()).then_with(|| a.to_ascii_uppercase().cmp(&b.to_ascii_uppercase())),
+    }
+}
+
+/// STEP exports of NX models
+pub struct Step;
+
+impl CleanupRule for Step {
+    fn name(&self) -> &'static str { "step" }
+
+    fn defaults(&self) -> Settings {
+        Settings {
+            folders: String::from("**/Fab/**/STEP"),
+            files: String::from("(?i)*.{stp,step}"),
+            retention: Duration::from_secs(90 * DAY),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wax::{Glob, Pattern};
+
+    use super::*;
+
+    #[test]
+    fn revisions() {
+        assert_eq!(revision("1210123A-1_Rev2"), Some(("1210123A-1", "2")));
+        assert_eq!(revision("1210123A-1 rev B"), Some(("1210123A-1", "B")));
+        assert_eq!(revision("1210123A-1-REV.C"), Some(("1210123A-1", "C")));
+        assert_eq!(revision("Reverse_Rev3"), Some(("Reverse", "3")));
+    }
+
+    #[test]
+    fn no_revision() {
+        for stem in ["1210123A-1", "1210123A-1Rev2", "1210123A-1_Rev", "_Rev2", "Rev2"] {
+            assert_eq!(revision(stem), None, "{}", stem);
+        }
+    }
+
+    #[test]
+    fn compare() {
+        assert_eq!(compare_revisions("2", "10"), Ordering::Less);
+        assert_eq!(compare_revisions("10", "9"), Ordering::Greater);
+        assert_eq!(compare_revisions("B", "A"), Ordering::Greater);
+        assert_eq!(compare_revisions("b", "A"), Ordering::Greater);
+        assert_eq!(compare_revisions("a", "A"), Ordering::Equal);
+        assert_eq!(compare_revisions("AA", "Z"), Ordering::Greater);
+        assert_eq!(compare_revisions("02", "2"), Ordering::Equal);
+    }
+
+    #[test]
+    fn superseded() {
+        let folder = ["1210123A-1_RevA.pdf", "1210123A-1_revB.PDF", "1210123A-2_Rev1.pdf", "1210123A-2_Rev3.dxf"].map(OsStr::new);
+        let keep = |name: &str| SupersededPdf.keep(Path::new(name), &[], &folder).unwrap();
+
+        assert!(keep("1210123A-1_RevA.pdf").is_none());
+        assert!(matches!(keep("1210123A-1_revB.PDF"), Some(KeepReason::Rule(_))));
+        assert!(matches!(keep("1210123A-2_Rev1.pdf"), Some(KeepReason::Rule(_))));
+        assert!(matches!(keep("1210123A-2.pdf"), Some(KeepReason::Rule(_))));
+    }
+
+    #[test]
+    fn file_globs_ignore_case() {
+        for (rule, name) in [(by_name("dxf"), "A.DXF"), (by_name("nc"), "A.NC"), (by_name("pdf"), "A_RevB.PDF"), (by_name("step"), "A.STP"), (by_name("step"), "a.Step")] {
+            let files = rule.unwrap().defaults().files;
+            assert!(Glob::new(&files).unwrap().is_match(name), "{} {}", files, name);
+        }
+    }
+}
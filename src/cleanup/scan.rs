@@ -0,0 +1,607 @@
+//! Single-pass scanner
+//!
+//! A root is walked once for all of its rules, and each folder is only
+//! listed once. The listing tells which entries are folders, gives the
+//! metadata of the files (which, on Windows, comes with the listing
+//! rather than needing another round trip to the server) and shows
+//! whether the folder holds a `.keep` marker or an overrides file.
+//!
+//! Rule folder globs are matched one path component at a time as the
+//! walk goes down, so a subtree is pruned as soon as no rule's folder
+//! glob can match anything under it. Files are matched against the
+//! rule's file glob from the same listing, rather than a second walk.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chrono::{DateTime, Local};
+use wax::{BuildError, Glob, Pattern};
+
+use crate::error::Error;
+use crate::throttle::Throttle;
+use super::index::{self, Index, IndexedFile};
+use super::overrides::{Applied, Overrides, OVERRIDES_FILE};
+use super::{days, name, AgeFrom, Candidate, Exclusions, FileInfo, Found, Kept, KeepReason, Orphan, Rule, KEEP_MARKER, STAGED_SUFFIX};
+
+/// A folder glob, split into path components
+///
+/// Each component is matched on its own, so alternatives in a folder
+/// glob (i.e. `{DXF,Dxf}`) must not contain a `/`.
+pub(super) struct FolderPattern(Vec<Component>);
+
+enum Component {
+    /// `**`, which matches any number of components
+    Tree,
+
+    /// a glob matching a single component
+    Name(Glob<'static>),
+}
+
+/// Positions in a [`FolderPattern`] that a path has reached
+///
+/// A path that has no positions left can never match, and a path that
+/// has reached the end of the pattern matches.
+pub(super) type States = Vec<usize>;
+
+impl FolderPattern {
+    /// Split a folder glob (relative to a root) into components
+    pub fn new(glob: &str) -> Result<Self, BuildError> {
+        let components = glob.split('/')
+            .filter(|c| !c.is_empty())
+            .map(|c| match c {
+                "**" => Ok(Component::Tree),
+                _ => Glob::new(c).map(|glob| Component::Name(glob.into_owned())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok( Self(components) )
+    }
+
+    /// States of the root
+    pub fn start(&self) -> States {
+        self.closure(vec![0])
+    }
+
+    /// States of a folder, from the states of its parent
+    pub fn step(&self, states: &States, name: &str) -> States {
+        let mut next = Vec::new();
+        for &i in states {
+            match self.0.get(i) {
+                Some(Component::Tree) => next.push(i),
+                Some(Component::Name(glob)) if glob.is_match(name) => next.push(i + 1),
+                _ => (),
+            }
+        }
+
+        self.closure(next)
+    }
+
+    /// Whether a folder in these states matches
+    pub fn matches(&self, states: &States) -> bool {
+        states.contains(&self.0.len())
+    }
+
+    // `**` can also match no components, so whatever follows it can be matched too
+    fn closure(&self, mut states: States) -> States {
+        let mut i = 0;
+        while i < states.len() {
+            if let Some(Component::Tree) = self.0.get(states[i]) {
+                let next = states[i] + 1;
+                if !states.contains(&next) {
+                    states.push(next);
+                }
+            }
+            i += 1;
+        }
+
+        states.sort_unstable();
+        states.dedup();
+        states
+    }
+}
+
+/// A folder matched by a rule, whose files are candidates
+#[derive(Clone)]
+struct Matched<'r> {
+    rule: &'r Rule,
+    dir: PathBuf,
+    applied: Applied,
+}
+
+/// A file in a listing
+enum Entry {
+    /// metadata has not been read yet
+    Unread(fs::DirEntry),
+
+    /// metadata has been read (or was taken from the index)
+    Read { size: u64, modified: DateTime<Local> },
+}
+
+/// Contents of a folder
+struct Listing {
+    dirs: Vec<OsString>,
+    files: BTreeMap<OsString, Entry>,
+    keep: bool,
+    overrides: bool,
+
+    /// whether every entry was read without errors
+    complete: bool,
+
+    /// whether the listing was taken from the index
+    indexed: bool,
+
+    /// whether a file from the index turned out to have changed
+    stale: Cell<bool>,
+}
+
+impl Listing {
+    fn read(dir: &Path, throttle: &Throttle, errors: &mut Vec<Error>) -> io::Result<Self> {
+        throttle.wait();
+        let mut listing = Self {
+            dirs: Vec::new(),
+            files: BTreeMap::new(),
+            keep: false,
+            overrides: false,
+            complete: true,
+            indexed: false,
+            stale: Cell::new(false),
+        };
+
+        for entry in fs::read_dir(dir)? {
+            let (entry, file_type) = match entry.and_then(|e| e.file_type().map(|t| (e, t))) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    errors.push(Error::new(dir, e));
+                    listing.complete = false;
+                    continue;
+                },
+            };
+
+            let name = entry.file_name();
+            if file_type.is_dir() {
+                listing.dirs.push(name);
+            } else if name == KEEP_MARKER {
+                listing.keep = true;
+            } else if name == OVERRIDES_FILE {
+                listing.overrides = true;
+            } else if file_type.is_file() {
+                listing.files.insert(name, Entry::Unread(entry));
+            }
+        }
+
+        listing.dirs.sort_unstable();
+        Ok( listing )
+    }
+
+    /// Read the metadata of all files (errors are left to be reported when the file is used)
+    fn read_files(&mut self, throttle: &Throttle) {
+        for entry in self.files.values_mut() {
+            if let Entry::Unread(dir_entry) = entry {
+                throttle.wait();
+                match dir_entry.metadata().and_then(|m| Ok((m.len(), m.modified()?))) {
+                    Ok((size, modified)) => *entry = Entry::Read { size, modified: modified.into() },
+                    Err(_) => self.complete = false,
+                }
+            }
+        }
+    }
+
+    fn from_index(dir: index::Dir) -> Self {
+        let files = dir.files.unwrap_or_default().into_iter()
+            .map(|f| (OsString::from(f.name), Entry::Read { size: f.size, modified: f.modified }))
+            .collect();
+
+        Self {
+            dirs: dir.dirs.into_iter().map(OsString::from).collect(),
+            files,
+            keep: dir.keep,
+            overrides: dir.overrides,
+            complete: true,
+            indexed: true,
+            stale: Cell::new(false),
+        }
+    }
+
+    /// Index entry for the listing, if it can be indexed
+    fn to_index(&self, modified: DateTime<Local>, with_files: bool) -> Option<index::Dir> {
+        if !self.complete {
+            return None;
+        }
+
+        // names that are not valid Unicode are not indexed
+        let dirs = self.dirs.iter()
+            .map(|name| name.to_str().map(String::from))
+            .collect::<Option<_>>()?;
+        let files = match with_files {
+            true => Some(self.files.iter()
+                .map(|(name, entry)| match entry {
+                    Entry::Read { size, modified } => Some(IndexedFile { name: name.to_str()?.to_string(), size: *size, modified: *modified }),
+                    Entry::Unread(_) => None,
+                })
+                .collect::<Option<_>>()?),
+            false => None,
+        };
+
+        Some(index::Dir { modified, dirs, files, keep: self.keep, overrides: self.overrides })
+    }
+
+    /// Path of a file in the listing, matching its name case-insensitively
+    ///
+    /// The Jobs share does not tell `part.DXF` from `part.dxf`, so files
+    /// are paired with their companions the same way.
+    fn find(&self, dir: &Path, path: &Path) -> Option<PathBuf> {
+        if path.parent() != Some(dir) {
+            return None;
+        }
+
+        let name = path.file_name()?;
+        if self.files.contains_key(name) {
+            return Some(path.to_path_buf());
+        }
+
+        let name = name.to_str()?;
+        self.files.keys()
+            .find(|other| other.to_str().is_some_and(|other| other.eq_ignore_ascii_case(name)))
+            .map(|other| dir.join(other))
+    }
+
+    /// Size and modified time of a file, from the listing if it is in it
+    fn file_info(&self, dir: &Path, path: &Path, throttle: &Throttle) -> io::Result<FileInfo> {
+        if path.parent() != Some(dir) {
+            throttle.wait();
+            return FileInfo::read(path);
+        }
+
+        match path.file_name().and_then(|name| self.files.get(name)) {
+            Some(Entry::Unread(entry)) => {
+                throttle.wait();
+                FileInfo::from_metadata(path, &entry.metadata()?)
+            },
+            Some(Entry::Read { size, modified }) => Ok(FileInfo { path: path.to_path_buf(), size: *size, modified: *modified }),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+/// A folder waiting to be listed, and what it inherits from its parents
+struct Folder<'r> {
+    path: PathBuf,
+    states: Vec<States>,
+    inherited: Vec<Matched<'r>>,
+    overrides: Vec<Arc<Overrides>>,
+}
+
+/// Walks a root for all of its rules
+pub(super) struct Scanner<'a> {
+    pub root: &'a Path,
+    pub rules: &'a [Rule],
+    pub age_from: AgeFrom,
+    pub exclusions: &'a Exclusions,
+    pub workers: usize,
+    pub throttle: &'a Throttle,
+    pub index: Option<&'a Index>,
+}
+
+impl Scanner<'_> {
+    /// Walk the whole root
+    ///
+    /// The root is listed first, then the folders in it (the job
+    /// folders) are shared out between the workers.
+    pub fn scan(&self, found: &mut Found) {
+        let root = Folder {
+            path: self.root.to_path_buf(),
+            states: self.rules.iter().map(|rule| rule.folders.start()).collect(),
+            inherited: Vec::new(),
+            overrides: Vec::new(),
+        };
+        let jobs = Mutex::new(self.visit(root, found).into_iter());
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.workers.max(1))
+                .map(|_| scope.spawn(|| {
+                    let mut found = Found::default();
+                    while let Some(job) = next(&jobs) {
+                        self.walk(job, &mut found);
+                    }
+                    found
+                }))
+                .collect();
+
+            for worker in workers {
+                match worker.join() {
+                    Ok(other) => found.extend(other),
+                    Err(panic) => std::panic::resume_unwind(panic),
+                }
+            }
+        });
+
+        // workers finish in any order
+        found.candidates.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+        found.kept.sort_by(|a, b| a.path.cmp(&b.path));
+        found.orphans.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+    }
+
+    /// Walk a folder and everything under it, depth first
+    fn walk<'r>(&'r self, folder: Folder<'r>, found: &mut Found) {
+        let mut stack = vec![folder];
+        while let Some(folder) = stack.pop() {
+            let subfolders = self.visit(folder, found);
+            stack.extend(subfolders.into_iter().rev());
+        }
+    }
+
+    /// List a folder, find files in it and return the subfolders to walk
+    fn visit<'r>(&'r self, folder: Folder<'r>, found: &mut Found) -> Vec<Folder<'r>> {
+        let Folder { path: dir, states, inherited, mut overrides } = folder;
+
+        let needs_files = !inherited.is_empty()
+            || self.rules.iter().zip(&states).any(|(rule, states)| rule.folders.matches(states));
+        let listing = match self.list(&dir, needs_files, found) {
+            Ok(listing) => listing,
+            Err(e) => {
+                found.errors.push(Error::new(&dir, e));
+                return Vec::new();
+            },
+        };
+
+        if listing.keep {
+            log::info!("Skipping `{}` (`{}` marker)", dir.display(), KEEP_MARKER);
+            return Vec::new();
+        }
+
+        if listing.overrides {
+            self.throttle.wait();
+            match Overrides::read(&dir) {
+                Ok(Some(o)) => {
+                    log::info!("Using overrides from `{}`", dir.join(OVERRIDES_FILE).display());
+                    overrides.push(Arc::new(o));
+                },
+                Ok(None) => (),
+                Err(e) => found.errors.push(Error::new(&dir.join(OVERRIDES_FILE), e)),
+            }
+        }
+
+        let mut matched = inherited;
+        for (rule, states) in self.rules.iter().zip(&states) {
+            if rule.folders.matches(states) {
+                let applied = Applied::new(rule, overrides.iter().map(Arc::as_ref));
+                matched.push(Matched { rule, dir: dir.clone(), applied });
+            }
+        }
+        for m in &matched {
+            self.find_files(&dir, &listing, m, found);
+            self.find_orphans(&dir, &listing, m, found);
+        }
+        if !matched.is_empty() {
+            find_staged(&dir, &listing, found);
+        }
+        if let (Some(index), true) = (self.index, listing.stale.get()) {
+            index.forget(&dir);
+        }
+
+        // only file globs that reach into subfolders (i.e. `**/*.dxf`) carry on down
+        let deep: Vec<Matched> = matched.into_iter()
+            .filter(|m| m.applied.is_deep())
+            .collect();
+
+        let mut subfolders = Vec::new();
+        for name in &listing.dirs {
+            let path = dir.join(name);
+            if let Some(reason) = self.exclusions.excluded(path.strip_prefix(self.root).unwrap_or(&path)) {
+                log::info!("Skipping `{}` ({})", path.display(), reason);
+                continue;
+            }
+
+            let name = name.to_string_lossy();
+            let next: Vec<States> = self.rules.iter()
+                .zip(&states)
+                .map(|(rule, states)| rule.folders.step(states, &name))
+                .collect();
+
+            if deep.is_empty() && next.iter().all(Vec::is_empty) {
+                log::debug!("Pruning `{}`", path.display());
+                continue;
+            }
+
+            subfolders.push(Folder { path, states: next, inherited: deep.clone(), overrides: overrides.clone() });
+        }
+
+        subfolders
+    }
+
+    /// List a folder, or take it from the index if it has not changed
+    fn list(&self, dir: &Path, needs_files: bool, found: &mut Found) -> io::Result<Listing> {
+        let Some(index) = self.index else {
+            log::debug!("Listing directory {}", dir.display());
+            return Listing::read(dir, self.throttle, &mut found.errors);
+        };
+
+        // read before listing, so that changes made while listing are picked up next time
+        self.throttle.wait();
+        let modified: DateTime<Local> = fs::metadata(dir)?.modified()?.into();
+        if let Some(indexed) = index.get(dir, modified, needs_files) {
+            log::debug!("Using index for directory {}", dir.display());
+            index.insert(dir.to_path_buf(), indexed.clone());
+
+            return Ok( Listing::from_index(indexed) );
+        }
+
+        log::debug!("Listing directory {}", dir.display());
+        let mut listing = Listing::read(dir, self.throttle, &mut found.errors)?;
+        if needs_files {
+            listing.read_files(self.throttle);
+        }
+        if let Some(indexed) = listing.to_index(modified, needs_files) {
+            index.insert(dir.to_path_buf(), indexed);
+        }
+
+        Ok( listing )
+    }
+
+    fn find_files(&self, dir: &Path, listing: &Listing, matched: &Matched, found: &mut Found) {
+        let Matched { rule, applied, .. } = matched;
+        let folder: Vec<&OsStr> = listing.files.keys().map(OsString::as_os_str).collect();
+
+        'files: for file_name in listing.files.keys() {
+            let path = dir.join(file_name);
+            if !applied.files.is_match(path.strip_prefix(&matched.dir).unwrap_or(&path)) {
+                continue;
+            }
+            *found.matched.entry((rule.name().into(), dir.to_path_buf())).or_default() += 1;
+
+            let file = match listing.file_info(dir, &path, self.throttle) {
+                Ok(file) => file,
+                Err(e) => {
+                    found.errors.push(Error::new(&path, e));
+                    continue;
+                },
+            };
+
+            let mut companions = Vec::new();
+            for companion in applied.companions(rule, &path) {
+                let companion = listing.find(dir, &companion).unwrap_or(companion);
+                match listing.file_info(dir, &companion, self.throttle) {
+                    Ok(info) => companions.push(info),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        found.kept.push(Kept { path: file.path, reason: KeepReason::MissingCompanion(companion) });
+                        continue 'files;
+                    },
+                    Err(e) => {
+                        found.errors.push(Error::new(&companion, e));
+                        continue 'files;
+                    },
+                }
+            }
+
+            let newest_companion = companions.iter().max_by_key(|c| c.modified);
+            let (source, modified) = match (self.age_from, newest_companion) {
+                (AgeFrom::Companion, Some(c)) => (&c.path, c.modified),
+                (AgeFrom::Newest, Some(c)) if c.modified > file.modified => (&c.path, c.modified),
+                _ => (&file.path, file.modified),
+            };
+
+            // filter out files modified within the retention period
+            let age = (Local::now() - modified).to_std().unwrap_or_default();
+            if age < applied.retention {
+                log::debug!("Skipping `{}` (last modified {} days ago)", file.path.display(), days(age));
+                continue;
+            }
+
+            // a file changed in place does not change its folder's modified time
+            if listing.indexed && std::iter::once(&file).chain(&companions).any(|f| { self.throttle.wait(); f.changed() }) {
+                log::debug!("Skipping `{}` (changed since it was indexed)", file.path.display());
+                listing.stale.set(true);
+                continue;
+            }
+
+            self.throttle.wait();
+            match rule.rule.keep(&file.path, &companions, &folder) {
+                Ok(None) => (),
+                Ok(Some(reason)) => {
+                    found.kept.push(Kept { path: file.path, reason });
+                    continue;
+                },
+                Err(e) => {
+                    found.errors.push(Error::new(&file.path, e));
+                    continue;
+                },
+            }
+
+            let reason = format!("`{}` last modified {} days ago", name(source), days(age));
+            found.candidates.push(Candidate { rule: rule.name().into(), file, companions, reason });
+        }
+    }
+
+    /// Find companion files in a folder whose file is not there
+    fn find_orphans(&self, dir: &Path, listing: &Listing, matched: &Matched, found: &mut Found) {
+        let rule = matched.rule;
+
+        for file_name in listing.files.keys() {
+            let path = dir.join(file_name);
+            let Some(missing) = rule.rule.companion_of(&path) else { continue };
+            if listing.find(dir, &missing).is_some() {
+                continue;
+            }
+
+            match listing.file_info(dir, &path, self.throttle) {
+                Ok(file) => {
+                    let age = (Local::now() - file.modified).to_std().unwrap_or_default();
+                    let expired = age >= matched.applied.retention;
+                    found.orphans.push(Orphan { rule: rule.name().into(), file, missing, expired });
+                },
+                Err(e) => found.errors.push(Error::new(&path, e)),
+            }
+        }
+    }
+}
+
+/// Report files left behind by an interrupted removal
+fn find_staged(dir: &Path, listing: &Listing, found: &mut Found) {
+    let staged = listing.files.keys()
+        .filter(|name| name.to_str().is_some_and(|name| name.ends_with(STAGED_SUFFIX)));
+
+    for name in staged {
+        let e = io::Error::other("left behind by an interrupted removal; rename or delete it by hand");
+        found.errors.push(Error::new(&dir.join(name), e));
+    }
+}
+
+fn next<T>(queue: &Mutex<impl Iterator<Item = T>>) -> Option<T> {
+    queue.lock().unwrap_or_else(|e| e.into_inner()).next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether a folder (relative to a root) matches a folder glob
+    fn matches(glob: &str, path: &str) -> bool {
+        let pattern = FolderPattern::new(glob).unwrap();
+        let states = path.split('/')
+            .filter(|name| !name.is_empty())
+            .fold(pattern.start(), |states, name| pattern.step(&states, name));
+
+        pattern.matches(&states)
+    }
+
+    #[test]
+    fn tree_matches_any_depth() {
+        for path in ["1210123/Fab/DXF", "1210123/Fab/Plates/DXF", "1210123/Shipment 2/Fab/Plates/Thick/DXF"] {
+            assert!(matches("**/Fab/**/DXF", path), "{}", path);
+        }
+
+        assert!(!matches("**/Fab/**/DXF", "1210123/DXF"));
+        assert!(!matches("**/Fab/**/DXF", "1210123/Fab/DXF/Old"));
+    }
+
+    #[test]
+    fn tree_matches_no_components() {
+        assert!(matches("**", ""));
+        assert!(matches("**/DXF", "DXF"));
+        assert!(matches("Fab/**", "Fab"));
+        assert!(matches("**/**/DXF", "DXF"));
+        assert!(!matches("Fab/**", ""));
+    }
+
+    #[test]
+    fn names() {
+        assert!(matches("*/Fab/{DXF,Dxf}", "1210123/Fab/Dxf"));
+        assert!(!matches("*/Fab/{DXF,Dxf}", "1210123/Fab/dxf"));
+        assert!(!matches("*/Fab", "1210123/Shipment 2/Fab"));
+    }
+
+    #[test]
+    fn pruned() {
+        let pattern = FolderPattern::new("*/Fab/**/DXF").unwrap();
+        let job = pattern.step(&pattern.start(), "1210123");
+
+        assert!(pattern.step(&job, "Drawings").is_empty());
+        assert!(!pattern.step(&job, "Fab").is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+//! Errors while walking or changing files on the share
+//!
+//! Every error is tied to the path it happened on and sorted into an
+//! [`ErrorKind`], so that a run can report how many permission
+//! denials, locked files and share disconnects it ran into.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Category of an [`Error`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorKind {
+    /// access was denied
+    Permission,
+
+    /// the file or directory does not exist (anymore)
+    NotFound,
+
+    /// the file is in use by another process
+    Locked,
+
+    /// the share could not be reached or was disconnected
+    Network,
+
+    /// anything else
+    Other,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Permission => "permission",
+            Self::NotFound => "not found",
+            Self::Locked => "locked",
+            Self::Network => "network",
+            Self::Other => "other",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// An I/O error on a path
+#[derive(Debug)]
+pub struct Error {
+    /// category of the error
+    pub kind: ErrorKind,
+
+    /// path the error happened on
+    pub path: PathBuf,
+
+    /// the underlying error
+    pub source: io::Error,
+}
+
+impl Error {
+    /// Categorise an I/O error on a path
+    pub fn new(path: &Path, source: io::Error) -> Self {
+        Self { kind: categorise(&source), path: path.to_path_buf(), source }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error on `{}`: {}", self.kind, self.path.display(), self.source)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn categorise(error: &io::Error) -> ErrorKind {
+    use io::ErrorKind as Io;
+
+    match error.kind() {
+        Io::PermissionDenied => return ErrorKind::Permission,
+        Io::NotFound => return ErrorKind::NotFound,
+        Io::ResourceBusy => return ErrorKind::Locked,
+        Io::TimedOut | Io::ConnectionReset | Io::ConnectionAborted | Io::NotConnected
+            | Io::NetworkDown | Io::NetworkUnreachable | Io::HostUnreachable
+            | Io::StaleNetworkFileHandle => return ErrorKind::Network,
+        _ => (),
+    }
+
+    // walk errors are wrapped, which hides the OS error code, but it is still in the message
+    let code = error.raw_os_error().or_else(|| {
+        let message = error.to_string();
+        let start = message.rfind("os error ")? + "os error ".len();
+
+        message[start..].trim_end_matches(')').parse().ok()
+    });
+
+    match code {
+        Some(code) if LOCKED.contains(&code) => ErrorKind::Locked,
+        Some(code) if NETWORK.contains(&code) => ErrorKind::Network,
+        Some(code) if PERMISSION.contains(&code) => ErrorKind::Permission,
+        _ => ErrorKind::Other,
+    }
+}
+
+// ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION, ERROR_USER_MAPPED_FILE
+#[cfg(windows)]
+const LOCKED: &[i32] = &[32, 33, 1224];
+
+// ERROR_BAD_NETPATH, ERROR_NETWORK_BUSY, ERROR_UNEXP_NET_ERR, ERROR_NETNAME_DELETED,
+// ERROR_BAD_NET_NAME, ERROR_SEM_TIMEOUT, ERROR_NETWORK_UNREACHABLE, ERROR_CONNECTION_ABORTED
+#[cfg(windows)]
+const NETWORK: &[i32] = &[53, 54, 59, 64, 67, 121, 1231, 1236];
+
+// ERROR_ACCESS_DENIED, ERROR_NETWORK_ACCESS_DENIED
+#[cfg(windows)]
+const PERMISSION: &[i32] = &[5, 65];
+
+// EBUSY, ETXTBSY
+#[cfg(not(windows))]
+const LOCKED: &[i32] = &[16, 26];
+
+// ENETDOWN, ENETUNREACH, ECONNRESET, ETIMEDOUT, EHOSTDOWN, EHOSTUNREACH, ESTALE
+#[cfg(not(windows))]
+const NETWORK: &[i32] = &[100, 101, 104, 110, 112, 113, 116];
+
+// EPERM, EACCES
+#[cfg(not(windows))]
+const PERMISSION: &[i32] = &[1, 13];
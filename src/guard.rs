@@ -0,0 +1,198 @@
+//! Checks that a root really is the Jobs share
+//!
+//! When the share is mounted (i.e. under `/mnt` on Linux), a failed
+//! mount leaves an empty folder, and a mistyped path can point at some
+//! other tree entirely. Before anything is removed from a root, a
+//! [`Guard`] checks that:
+//!
+//! - the root holds a sentinel file (`.prodctrl_root` by default),
+//! - it has at least a minimum number of job folders, and
+//! - most of its folders are named like jobs (see [`JobNumber`]).
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::jobs::JobNumber;
+
+/// Default sentinel file name
+pub const SENTINEL_FILE: &str = ".prodctrl_root";
+
+/// Checks for a root
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Guard {
+    /// file that must exist in the root (`None` to not check)
+    pub sentinel: Option<String>,
+
+    /// minimum number of job folders in the root
+    pub min_jobs: usize,
+
+    /// minimum fraction of the folders in the root that are job folders
+    pub min_job_ratio: f64,
+}
+
+impl Default for Guard {
+    fn default() -> Self {
+        Self {
+            sentinel: Some(String::from(SENTINEL_FILE)),
+            min_jobs: 20,
+            min_job_ratio: 0.5,
+        }
+    }
+}
+
+/// Why a root failed its checks
+#[derive(Debug)]
+pub enum GuardError {
+    /// the root could not be read (i.e. it is not mounted)
+    Unreadable(PathBuf, io::Error),
+
+    /// the sentinel file is missing
+    NoSentinel(PathBuf),
+
+    /// there are too few job folders
+    TooFewJobs {
+        /// the root
+        root: PathBuf,
+        /// job folders found
+        jobs: usize,
+        /// job folders required
+        min: usize,
+    },
+
+    /// too few of the folders are named like jobs
+    NotJobFolders {
+        /// the root
+        root: PathBuf,
+        /// job folders found
+        jobs: usize,
+        /// all folders found
+        folders: usize,
+    },
+}
+
+impl fmt::Display for GuardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreadable(root, e) => write!(f, "could not read root `{}`: {}", root.display(), e),
+            Self::NoSentinel(path) => write!(f, "sentinel file `{}` does not exist", path.display()),
+            Self::TooFewJobs { root, jobs, min } => write!(f, "root `{}` has {} job folders (expected at least {})", root.display(), jobs, min),
+            Self::NotJobFolders { root, jobs, folders } => write!(f, "only {} of the {} folders in root `{}` are named like jobs", jobs, folders, root.display()),
+        }
+    }
+}
+
+impl std::error::Error for GuardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Unreadable(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Guard {
+    /// Check a root, returning the number of job folders in it
+    pub fn check(&self, root: &Path) -> Result<usize, GuardError> {
+        let unreadable = |e| GuardError::Unreadable(root.to_path_buf(), e);
+
+        if let Some(sentinel) = &self.sentinel {
+            let path = root.join(sentinel);
+            if !path.is_file() {
+                return Err(GuardError::NoSentinel(path));
+            }
+        }
+
+        let (mut folders, mut jobs) = (0, 0);
+        for entry in fs::read_dir(root).map_err(unreadable)? {
+            let entry = entry.map_err(unreadable)?;
+            if !entry.file_type().map_err(unreadable)?.is_dir() {
+                continue;
+            }
+
+            folders += 1;
+            if entry.file_name().to_string_lossy().parse::<JobNumber>().is_ok() {
+                jobs += 1;
+            }
+        }
+
+        if jobs < self.min_jobs {
+            return Err(GuardError::TooFewJobs { root: root.to_path_buf(), jobs, min: self.min_jobs });
+        }
+        if (jobs as f64) < self.min_job_ratio * folders as f64 {
+            return Err(GuardError::NotJobFolders { root: root.to_path_buf(), jobs, folders });
+        }
+
+        Ok( jobs )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    /// Make a root holding the given folders (and the sentinel file, if `sentinel`)
+    fn root(name: &str, folders: &[&str], sentinel: bool) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("prodctrl_guard_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        for folder in folders {
+            fs::create_dir(root.join(folder)).unwrap();
+        }
+        if sentinel {
+            File::create(root.join(SENTINEL_FILE)).unwrap();
+        }
+
+        root
+    }
+
+    fn guard(min_jobs: usize) -> Guard {
+        Guard { min_jobs, ..Guard::default() }
+    }
+
+    #[test]
+    fn passes() {
+        let root = root("passes", &["1210123", "1210124", "1210124A", "Templates"], true);
+
+        assert_eq!(guard(3).check(&root).unwrap(), 3);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn no_sentinel() {
+        let root = root("no_sentinel", &["1210123", "1210124"], false);
+
+        assert!(matches!(guard(1).check(&root), Err(GuardError::NoSentinel(path)) if path == root.join(SENTINEL_FILE)));
+        assert_eq!(Guard { sentinel: None, ..guard(1) }.check(&root).unwrap(), 2);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn too_few_jobs() {
+        let root = root("too_few_jobs", &["1210123", "1210124"], true);
+
+        assert!(matches!(guard(3).check(&root), Err(GuardError::TooFewJobs { jobs: 2, min: 3, .. })));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn not_job_folders() {
+        let root = root("not_job_folders", &["1210123", "Music", "Photos", "Backups"], true);
+
+        assert!(matches!(guard(1).check(&root), Err(GuardError::NotJobFolders { jobs: 1, folders: 4, .. })));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn unreadable() {
+        let root = std::env::temp_dir().join(format!("prodctrl_guard_missing_{}", std::process::id()));
+
+        assert!(matches!(Guard { sentinel: None, ..guard(1) }.check(&root), Err(GuardError::Unreadable(..))));
+    }
+}
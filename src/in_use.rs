@@ -0,0 +1,237 @@
+//! Checks for files that are still in use
+//!
+//! A DXF file can be old enough to remove and still be in the middle of
+//! being re-exported from NX or imported into Sigmanest. Right before a
+//! file is removed, [`Activity`] looks for signs that it is in use:
+//!
+//! - a file in its folder was modified recently,
+//! - its folder holds an NX temp file or a Sigmanest lock file, or
+//! - it cannot be opened for exclusive access (on Windows; elsewhere,
+//!   only for writing).
+//!
+//! Files that look to be in use are deferred to a later run rather than
+//! removed. So are the files of a folder that cannot be listed (i.e. the
+//! share dropped out), since nothing can be told about them.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use wax::{BuildError, Glob, Pattern};
+
+use crate::cleanup::Candidate;
+use crate::error::{self, ErrorKind};
+use crate::throttle::Throttle;
+
+/// Settings for the in-use checks
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InUse {
+    /// minutes since any file in a folder was modified before its files can be removed
+    pub quiet_minutes: u64,
+
+    /// globs matching NX temp file names
+    pub temp_files: Vec<String>,
+
+    /// globs matching Sigmanest lock file names
+    pub lock_files: Vec<String>,
+
+    /// whether to try opening each file for exclusive access
+    pub probe: bool,
+}
+
+impl Default for InUse {
+    fn default() -> Self {
+        Self {
+            quiet_minutes: 30,
+            temp_files: vec![String::from("*.tmp"), String::from("~*")],
+            lock_files: vec![String::from("*.lck"), String::from("*.lock")],
+            probe: true,
+        }
+    }
+}
+
+/// Why a file looks to be in use
+#[derive(Debug, Clone)]
+pub enum Busy {
+    /// a file in the same folder was modified recently
+    Recent {
+        /// the recently modified file
+        path: PathBuf,
+        /// when it was modified
+        modified: DateTime<Local>,
+    },
+
+    /// the folder holds an NX temp file
+    TempFile(PathBuf),
+
+    /// the folder holds a Sigmanest lock file
+    LockFile(PathBuf),
+
+    /// the file is open in another process
+    Locked(PathBuf),
+
+    /// the folder could not be listed, so it cannot be told to be quiet
+    Unlisted(PathBuf),
+}
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Recent { path, modified } => write!(f, "`{}` was modified {}", path.display(), modified.format("%Y-%m-%d %H:%M")),
+            Self::TempFile(path) => write!(f, "NX temp file `{}`", path.display()),
+            Self::LockFile(path) => write!(f, "lock file `{}`", path.display()),
+            Self::Locked(path) => write!(f, "`{}` is open in another process", path.display()),
+            Self::Unlisted(path) => write!(f, "`{}` could not be listed", path.display()),
+        }
+    }
+}
+
+impl InUse {
+    /// Build the checks for a run, held to the run's throttle
+    pub fn activity<'t>(&self, throttle: &'t Throttle) -> Result<Activity<'t>, BuildError> {
+        let globs = |globs: &[String]| globs.iter()
+            .map(|glob| Glob::new(glob).map(Glob::into_owned))
+            .collect::<Result<Vec<_>, _>>();
+
+        Ok(Activity {
+            quiet: Duration::from_secs(self.quiet_minutes * 60),
+            temp_files: globs(&self.temp_files)?,
+            lock_files: globs(&self.lock_files)?,
+            probe: self.probe,
+            folders: RefCell::new(BTreeMap::new()),
+            throttle,
+        })
+    }
+}
+
+/// In-use checks for the files of a run
+///
+/// Each folder is only looked at once per run, the first time one of
+/// its files is checked. Every filesystem operation waits on the
+/// run's [`Throttle`], the same as the search and removal.
+#[derive(Debug)]
+pub struct Activity<'t> {
+    quiet: Duration,
+    temp_files: Vec<Glob<'static>>,
+    lock_files: Vec<Glob<'static>>,
+    probe: bool,
+    folders: RefCell<BTreeMap<PathBuf, Option<Busy>>>,
+    throttle: &'t Throttle,
+}
+
+impl Activity<'_> {
+    /// Check if a file or any of its companion files look to be in use
+    ///
+    /// A folder that cannot be listed is taken to be in use, and why it
+    /// could not be listed is added to `errors`.
+    pub fn check(&self, candidate: &Candidate, errors: &mut Vec<error::Error>) -> Option<Busy> {
+        for file in candidate.files() {
+            if let Some(busy) = file.path.parent().and_then(|folder| self.check_folder(folder, errors)) {
+                return Some(busy);
+            }
+        }
+
+        if self.probe {
+            for file in candidate.files() {
+                self.throttle.wait();
+
+                // other errors are left for the removal to report
+                let locked = probe(&file.path).is_err_and(|e| error::Error::new(&file.path, e).kind == ErrorKind::Locked);
+                if locked {
+                    return Some(Busy::Locked(file.path.clone()));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn check_folder(&self, folder: &Path, errors: &mut Vec<error::Error>) -> Option<Busy> {
+        if let Some(busy) = self.folders.borrow().get(folder) {
+            return busy.clone();
+        }
+
+        let busy = self.scan(folder).unwrap_or_else(|e| {
+            errors.push(error::Error::new(folder, e));
+            Some(Busy::Unlisted(folder.to_path_buf()))
+        });
+        self.folders.borrow_mut().insert(folder.to_path_buf(), busy.clone());
+
+        busy
+    }
+
+    fn scan(&self, folder: &Path) -> io::Result<Option<Busy>> {
+        self.throttle.wait();
+        let cutoff = Local::now() - self.quiet;
+
+        for entry in fs::read_dir(folder)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = Path::new(&name);
+
+            if self.temp_files.iter().any(|glob| glob.is_match(name)) {
+                return Ok(Some(Busy::TempFile(path)));
+            }
+            if self.lock_files.iter().any(|glob| glob.is_match(name)) {
+                return Ok(Some(Busy::LockFile(path)));
+            }
+
+            self.throttle.wait();
+            let modified = DateTime::<Local>::from(entry.metadata()?.modified()?);
+            if modified > cutoff {
+                return Ok(Some(Busy::Recent { path, modified }));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Try to open a file without letting any other process share it
+fn probe(path: &Path) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        options.share_mode(0);
+    }
+
+    options.open(path).map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cleanup::FileInfo;
+
+    #[test]
+    fn unlisted_folder_is_busy() {
+        let throttle = Throttle::unlimited();
+        let activity = InUse::default().activity(&throttle).unwrap();
+        let path = PathBuf::from("/no/such/folder/part.dxf");
+        let file = FileInfo { path: path.clone(), size: 0, modified: Local::now() };
+        let candidate = Candidate { rule: String::from("dxf"), file, companions: Vec::new(), reason: String::new() };
+
+        let mut errors = Vec::new();
+        assert!(matches!(activity.check(&candidate, &mut errors), Some(Busy::Unlisted(folder)) if folder == path.parent().unwrap()));
+        assert_eq!(errors.len(), 1);
+
+        // the folder is only listed (and its error reported) once
+        assert!(activity.check(&candidate, &mut errors).is_some());
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+//! Job numbers and job metadata
+//!
+//! Job folders on the share are named by job number: seven digits,
+//! optionally followed by a structure letter (i.e. `1210123` or
+//! `1210123A`). A [`JobNumber`] sorts by number, then structure, with
+//! the job itself before any of its structures.
+//!
+//! Metadata for jobs (customer, status and ship date) can be read from
+//! a local CSV file with [`Jobs::read`]:
+//!
+//! ```csv
+//! job,customer,status,ship_date
+//! 1210123,Acme Steel,shipped,2024-03-15
+//! 1210124A,Acme Steel,active,
+//! 1190042,Northwind,closed,2021-11-02
+//! ```
+//!
+//! Lists of jobs (such as protected jobs) are read with [`read_list`].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+const DIGITS: usize = 7;
+
+/// A job number, with an optional structure letter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct JobNumber {
+    /// the job number (i.e. `1210123`)
+    pub number: u32,
+
+    /// structure letter, always uppercase (i.e. `A`)
+    pub structure: Option<char>,
+}
+
+/// A folder name or value that is not a job number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidJobNumber(pub String);
+
+impl fmt::Display for InvalidJobNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` is not a job number (expected {} digits and an optional structure letter)", self.0, DIGITS)
+    }
+}
+
+impl std::error::Error for InvalidJobNumber {}
+
+impl JobNumber {
+    /// The job without its structure letter
+    pub fn job(&self) -> Self {
+        Self { number: self.number, structure: None }
+    }
+}
+
+impl FromStr for JobNumber {
+    type Err = InvalidJobNumber;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidJobNumber(s.to_string());
+
+        let (digits, suffix) = s.split_at_checked(DIGITS).ok_or_else(invalid)?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let mut suffix = suffix.chars();
+        let structure = match (suffix.next(), suffix.next()) {
+            (None, _) => None,
+            (Some(c), None) if c.is_ascii_alphabetic() => Some(c.to_ascii_uppercase()),
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self { number: digits.parse().map_err(|_| invalid())?, structure })
+    }
+}
+
+impl TryFrom<String> for JobNumber {
+    type Error = InvalidJobNumber;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<JobNumber> for String {
+    fn from(job: JobNumber) -> Self {
+        job.to_string()
+    }
+}
+
+impl fmt::Display for JobNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:0width$}", self.number, width = DIGITS)?;
+        if let Some(structure) = self.structure {
+            write!(f, "{}", structure)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where a job is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// the job is still in production
+    Active,
+
+    /// the job has shipped
+    Shipped,
+
+    /// the job is closed out
+    Closed,
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Active => "active",
+            Self::Shipped => "shipped",
+            Self::Closed => "closed",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Metadata for a job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    /// the job
+    pub job: JobNumber,
+
+    /// customer the job is for
+    pub customer: String,
+
+    /// where the job is in its life
+    pub status: JobStatus,
+
+    /// when the job shipped (or is due to)
+    pub ship_date: Option<NaiveDate>,
+}
+
+/// Metadata for all jobs in a jobs CSV file
+#[derive(Debug, Default, Clone)]
+pub struct Jobs(BTreeMap<JobNumber, JobInfo>);
+
+impl Jobs {
+    /// Read a jobs CSV file
+    ///
+    /// If a job is listed more than once, the last row is used.
+    pub fn read(path: &Path) -> csv::Result<Self> {
+        let mut jobs = BTreeMap::new();
+        for row in csv::Reader::from_path(path)?.deserialize() {
+            let info: JobInfo = row?;
+            jobs.insert(info.job, info);
+        }
+
+        Ok( Self(jobs) )
+    }
+
+    /// Look up a job
+    ///
+    /// A structure that is not listed uses the metadata of its job.
+    pub fn get(&self, job: &JobNumber) -> Option<&JobInfo> {
+        self.0.get(job)
+            .or_else(|| self.0.get(&job.job()))
+    }
+
+    /// All listed jobs, in order
+    pub fn iter(&self) -> impl Iterator<Item = &JobInfo> {
+        self.0.values()
+    }
+}
+
+/// Read a list of job numbers, one per line
+///
+/// Blank lines and anything after a `#` are ignored:
+///
+/// ```text
+/// # warranty
+/// 1210123     # until 2027
+/// 1190042A
+/// ```
+pub fn read_list(path: &Path) -> io::Result<BTreeSet<JobNumber>> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(s: &str) -> JobNumber {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(job("1210123"), JobNumber { number: 1210123, structure: None });
+        assert_eq!(job("1210123a"), JobNumber { number: 1210123, structure: Some('A') });
+        assert_eq!(job("0010123").to_string(), "0010123");
+    }
+
+    #[test]
+    fn malformed() {
+        for s in ["", "121012", "12101234", "1210123AB", "1210123-", "121O123", "1210123 ", "+210123", "1210123é", "12101é3"] {
+            assert_eq!(s.parse::<JobNumber>(), Err(InvalidJobNumber(s.to_string())), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn order() {
+        let mut jobs = vec![job("1210124"), job("1210123B"), job("1190042"), job("1210123"), job("1210123A")];
+        jobs.sort();
+
+        assert_eq!(jobs, [job("1190042"), job("1210123"), job("1210123A"), job("1210123B"), job("1210124")]);
+        assert_eq!(job("1210123B").job(), job("1210123"));
+    }
+}
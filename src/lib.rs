@@ -1,4 +1,16 @@
-
 #![warn(missing_docs)]
 
 //! Production control utilities
+
+pub mod archive;
+pub mod audit;
+pub mod breaker;
+pub mod cleanup;
+pub mod error;
+pub mod guard;
+pub mod in_use;
+pub mod jobs;
+pub mod nxlog;
+pub mod paths;
+pub mod report;
+pub mod throttle;
@@ -1,11 +1,189 @@
+//! Production control tools
 
-fn main() {
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use wax::Glob;
+
+use prodctrl::audit::{self, Action, Entry, Query};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Production control tools")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Audit journal of removed files
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AuditCommand {
+    /// Search the audit journal
+    Query {
+        /// Audit journal to search
+        #[arg(long, default_value = audit::JOURNAL_FILE)]
+        journal: PathBuf,
+
+        /// Only entries from this run
+        #[arg(long)]
+        run: Option<String>,
+
+        /// Only entries for this action
+        #[arg(long, value_enum)]
+        action: Option<QueryAction>,
+
+        /// Only entries whose path matches this glob
+        #[arg(long)]
+        path: Option<String>,
+
+        /// Only entries from this user
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Only entries on or after this date (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        since: Option<DateTime<Local>>,
+
+        /// Only entries before this date (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        until: Option<DateTime<Local>>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum QueryAction {
+    /// files that were deleted
+    Delete,
+
+    /// files that were moved into quarantine
+    Quarantine,
+
+    /// files that were restored from quarantine
+    Restore,
+
+    /// files that were deleted after being archived
+    Archive,
+
+    /// files that were extracted from an archive
+    Extract,
+
+    /// files that were moved to cold storage
+    Move,
+}
+
+impl From<QueryAction> for Action {
+    fn from(value: QueryAction) -> Self {
+        match value {
+            QueryAction::Delete => Self::Delete,
+            QueryAction::Quarantine => Self::Quarantine,
+            QueryAction::Restore => Self::Restore,
+            QueryAction::Archive => Self::Archive,
+            QueryAction::Extract => Self::Extract,
+            QueryAction::Move => Self::Move,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    /// one JSON object per line
+    Json,
+
+    /// CSV with a header row
+    Csv,
+}
+
+/// A row of CSV output
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    time: DateTime<Local>,
+    run: &'a str,
+    host: &'a str,
+    user: &'a str,
+    action: Action,
+    path: &'a Path,
+    size: u64,
+    modified: DateTime<Local>,
+    sha256: &'a str,
+}
+
+impl<'a> From<&'a Entry> for Row<'a> {
+    fn from(entry: &'a Entry) -> Self {
+        Self {
+            time: entry.time,
+            run: &entry.run.run,
+            host: &entry.run.host,
+            user: &entry.run.user,
+            action: entry.action,
+            path: &entry.path,
+            size: entry.size,
+            modified: entry.modified,
+            sha256: &entry.sha256,
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
     pretty_env_logger::init();
+    let args = Args::parse();
+
+    match args.command {
+        Command::Audit { command: AuditCommand::Query { journal, run, action, path, user, since, until, format } } => {
+            let query = Query {
+                run,
+                action: action.map(Into::into),
+                path: path.as_deref().map(Glob::new).transpose()?.map(Glob::into_owned),
+                user,
+                since,
+                until,
+            };
+
+            let entries: Vec<Entry> = audit::read(&journal)?.into_iter()
+                .filter(|entry| query.matches(entry))
+                .collect();
+            log::info!("{} matching entries in `{}`", entries.len(), journal.display());
+
+            match format {
+                Format::Json => {
+                    let mut out = io::stdout().lock();
+                    for entry in &entries {
+                        writeln!(out, "{}", serde_json::to_string(entry)?)?;
+                    }
+                },
+                Format::Csv => {
+                    let mut wtr = csv::Writer::from_writer(io::stdout());
+                    for entry in &entries {
+                        wtr.serialize(Row::from(entry))?;
+                    }
+                    wtr.flush()?;
+                },
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Parse a date (YYYY-MM-DD) as midnight local time
+fn parse_date(value: &str) -> Result<DateTime<Local>, String> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|e| e.to_string())?;
 
-    let msg = "base binary not implemented";
-    log::trace!("{}", msg);
-    log::debug!("{}", msg);
-    log::info!("{}", msg);
-    log::warn!("{}", msg);
-    log::error!("{}", msg);
-}
\ No newline at end of file
+    Local.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .ok_or_else(|| format!("`{}` is not a valid local date", value))
+}
@@ -0,0 +1,218 @@
+//! NX DXF export `.log` files
+//!
+//! When NX exports a DXF file, it writes a `.log` file next to it that
+//! records the translation. This parses the parts of it that we care
+//! about: the source part, when and by whom it was exported, the units
+//! used and any warnings or errors. Only a log that shows the export
+//! ran to completion without errors is a [clean](ExportLog::is_clean)
+//! export.
+//!
+//! Lines are read as `key : value` (or `key = value`) pairs, where the
+//! key is matched case-insensitively:
+//!
+//! ```text
+//! Input File      : C:\NX\1210123\1210123A-1.prt
+//! Date            : Mon Mar 04 10:15:22 2024
+//! User            : pmiller
+//! Units           : Inches
+//! *** WARNING : Spline approximated by polyline
+//! Translation Completed Successfully
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+
+const DATE_FORMATS: [&str; 5] = [
+    "%a %b %d %H:%M:%S %Y",
+    "%m/%d/%Y %H:%M:%S",
+    "%m/%d/%Y %I:%M:%S %p",
+    "%Y-%m-%d %H:%M:%S",
+    "%d-%b-%Y %H:%M:%S",
+];
+
+/// How far an export got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// the export ran to completion
+    Completed,
+
+    /// the export reported that it failed
+    Failed,
+
+    /// the log ends without the export completing or failing
+    Incomplete,
+}
+
+/// Contents of an NX DXF export `.log` file
+#[derive(Debug, Clone)]
+pub struct ExportLog {
+    /// NX part the DXF was exported from
+    pub part: Option<PathBuf>,
+
+    /// when the export was done
+    pub exported: Option<NaiveDateTime>,
+
+    /// who did the export
+    pub user: Option<String>,
+
+    /// units of the export (i.e. `Inches`)
+    pub units: Option<String>,
+
+    /// warnings reported during the export
+    pub warnings: Vec<String>,
+
+    /// errors reported during the export
+    pub errors: Vec<String>,
+
+    /// how far the export got
+    pub status: Status,
+}
+
+impl ExportLog {
+    /// Read and parse a `.log` file
+    pub fn read(path: &Path) -> io::Result<Self> {
+        // logs are not always UTF-8 (NX writes them in the system code page)
+        let text = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+
+        Ok( Self::parse(&text) )
+    }
+
+    /// Whether the log shows a completed export with no errors
+    pub fn is_clean(&self) -> bool {
+        self.status == Status::Completed && self.errors.is_empty()
+    }
+
+    /// Parse the contents of a `.log` file
+    pub fn parse(text: &str) -> Self {
+        let mut log = Self {
+            part: None,
+            exported: None,
+            user: None,
+            units: None,
+            warnings: Vec::new(),
+            errors: Vec::new(),
+            status: Status::Incomplete,
+        };
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (key, value) = match line.split_once([':', '=']) {
+                Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+                None => (String::new(), ""),
+            };
+
+            match key.as_str() {
+                "input file" | "input part" | "part" | "part file" | "source part" => log.part = Some(PathBuf::from(value)),
+                "date" | "export time" | "start time" | "translation started" => log.exported = parse_date(value),
+                "user" | "user name" | "username" => log.user = Some(value.to_string()),
+                "units" | "output units" => log.units = Some(value.to_string()),
+                _ => (),
+            }
+
+            // summary counts, such as `Errors : 0`
+            if value == "0" || value.eq_ignore_ascii_case("none") {
+                continue;
+            }
+
+            let lower = line.to_ascii_lowercase();
+            let message = || line.trim_start_matches(['*', ' ']).to_string();
+            if lower.contains("error") || lower.contains("fatal") {
+                log.errors.push(message());
+            } else if lower.contains("warning") {
+                log.warnings.push(message());
+            }
+
+            if lower.contains("completed successfully") || lower.contains("translation successful") {
+                log.status = Status::Completed;
+            } else if lower.contains("failed") || lower.contains("aborted") || lower.contains("terminated") {
+                log.status = Status::Failed;
+            }
+        }
+
+        log
+    }
+}
+
+impl fmt::Display for ExportLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.status, self.errors.first()) {
+            (Status::Completed, None) => write!(f, "export completed"),
+            (Status::Completed, Some(error)) => write!(f, "export completed with errors ({})", error),
+            (Status::Failed, Some(error)) => write!(f, "export failed ({})", error),
+            (Status::Failed, None) => write!(f, "export failed"),
+            (Status::Incomplete, _) => write!(f, "export did not complete"),
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Option<NaiveDateTime> {
+    DATE_FORMATS.iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(value, fmt).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAN: &str = "\
+Input File      : C:\\NX\\1210123\\1210123A-1.prt
+Date            : Mon Mar 04 10:15:22 2024
+User            : pmiller
+Units           : Inches
+*** WARNING : Spline approximated by polyline
+Errors          : 0
+Translation Completed Successfully
+";
+
+    #[test]
+    fn clean() {
+        let log = ExportLog::parse(CLEAN);
+
+        assert_eq!(log.part, Some(PathBuf::from("C:\\NX\\1210123\\1210123A-1.prt")));
+        assert_eq!(log.exported.map(|date| date.to_string()).as_deref(), Some("2024-03-04 10:15:22"));
+        assert_eq!(log.user.as_deref(), Some("pmiller"));
+        assert_eq!(log.units.as_deref(), Some("Inches"));
+        assert_eq!(log.warnings, ["WARNING : Spline approximated by polyline"]);
+        assert!(log.errors.is_empty());
+        assert_eq!(log.status, Status::Completed);
+        assert!(log.is_clean());
+    }
+
+    #[test]
+    fn failed() {
+        let log = ExportLog::parse("\
+Input File : C:\\NX\\1210123\\1210123A-2.prt
+*** ERROR : Unable to open part
+Translation Failed
+");
+
+        assert_eq!(log.errors, ["ERROR : Unable to open part"]);
+        assert_eq!(log.status, Status::Failed);
+        assert!(!log.is_clean());
+        assert_eq!(log.to_string(), "export failed (ERROR : Unable to open part)");
+    }
+
+    #[test]
+    fn incomplete() {
+        let log = ExportLog::parse("\
+Input File : C:\\NX\\1210123\\1210123A-3.prt
+Date       : 03/04/2024 10:15:22
+");
+
+        assert_eq!(log.exported.map(|date| date.to_string()).as_deref(), Some("2024-03-04 10:15:22"));
+        assert_eq!(log.status, Status::Incomplete);
+        assert!(!log.is_clean());
+        assert!(!ExportLog::parse("").is_clean());
+    }
+
+    #[test]
+    fn completed_with_errors() {
+        let log = ExportLog::parse("*** ERROR : Bad curve\nTranslation Completed Successfully\n");
+
+        assert_eq!(log.status, Status::Completed);
+        assert!(!log.is_clean());
+    }
+}
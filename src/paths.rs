@@ -0,0 +1,302 @@
+//! Paths under the Jobs share
+//!
+//! Everything on the share follows the same layout: a folder per job,
+//! optionally split by structure or shipment, with a `Fab` folder that
+//! holds the fabrication outputs sorted into artifact folders:
+//!
+//! ```text
+//! \\hssieng\Jobs\1210123\Fab\Plates\DXF\1210123A-1.dxf
+//!                ^^^^^^^ ^^^ ^^^^^^ ^^^ ^^^^^^^^^^^^^^
+//!                job     Fab subtree    file
+//!                              artifact folder
+//!
+//! \\hssieng\Jobs\1210123\Shipment 2\Fab\NC\1210123A-1.nc
+//!                        ^^^^^^^^^^
+//!                        segment
+//! ```
+//!
+//! A [`JobPath`] is parsed from a path with [`JobPath::parse`] and can
+//! be turned back into one with [`JobPath::to_path`]. Folder names are
+//! matched case-insensitively, but kept as they are spelled on the share
+//! so that the path built back up is the one that was parsed.
+
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::jobs::{InvalidJobNumber, JobNumber};
+
+/// Name of the fabrication folder in a job
+pub const FAB: &str = "Fab";
+
+/// Kind of artifact folder in a `Fab` subtree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Artifact {
+    /// DXF files exported from NX
+    Dxf,
+
+    /// NC programs from Sigmanest
+    Nc,
+
+    /// drawing PDFs
+    Drawings,
+
+    /// STEP models
+    Step,
+}
+
+impl Artifact {
+    /// All artifact kinds
+    pub const ALL: [Self; 4] = [Self::Dxf, Self::Nc, Self::Drawings, Self::Step];
+
+    /// Name of the artifact folder
+    pub fn folder(&self) -> &'static str {
+        match self {
+            Self::Dxf => "DXF",
+            Self::Nc => "NC",
+            Self::Drawings => "Drawings",
+            Self::Step => "STEP",
+        }
+    }
+
+    /// Find the artifact kind of a folder name (case-insensitive)
+    pub fn from_folder(name: &str) -> Option<Self> {
+        Self::ALL.into_iter()
+            .find(|kind| kind.folder().eq_ignore_ascii_case(name))
+    }
+}
+
+impl fmt::Display for Artifact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.folder())
+    }
+}
+
+/// A path under a Jobs root, split into its parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobPath {
+    /// the Jobs root
+    pub root: PathBuf,
+
+    /// job folder name (i.e. `1210123`, see [`JobPath::job_number`])
+    pub job: String,
+
+    /// structure or shipment folder(s) between the job and `Fab`
+    pub segment: Option<PathBuf>,
+
+    /// name of the `Fab` folder, as spelled on the share (i.e. `FAB`)
+    pub fab_name: String,
+
+    /// folders between `Fab` and the artifact folder (i.e. `Plates`)
+    pub fab: PathBuf,
+
+    /// kind of artifact folder, if the path is in one
+    pub artifact: Option<Artifact>,
+
+    /// name of the artifact folder, as spelled on the share (i.e. `dxf`)
+    ///
+    /// [`Artifact::folder`] is used if this is not set.
+    pub artifact_name: Option<String>,
+
+    /// file (or folder) in the artifact folder, relative to it
+    pub file: Option<PathBuf>,
+}
+
+/// Why a path is not a [`JobPath`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// the path is not under the root
+    NotUnderRoot(PathBuf),
+
+    /// the path is the root itself
+    NoJob(PathBuf),
+
+    /// the path has no `Fab` folder under the job
+    NoFab(PathBuf),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotUnderRoot(path) => write!(f, "`{}` is not under the Jobs root", path.display()),
+            Self::NoJob(path) => write!(f, "`{}` is not in a job folder", path.display()),
+            Self::NoFab(path) => write!(f, "`{}` is not in a `{}` folder", path.display(), FAB),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl JobPath {
+    /// Path to a job's `Fab` folder
+    pub fn new(root: &Path, job: &str) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            job: job.to_string(),
+            segment: None,
+            fab_name: FAB.to_string(),
+            fab: PathBuf::new(),
+            artifact: None,
+            artifact_name: None,
+            file: None,
+        }
+    }
+
+    /// Split a path under `root` into its parts
+    ///
+    /// The `Fab` folder and artifact folders are matched
+    /// case-insensitively. The first artifact folder under `Fab` is
+    /// used, and everything after it is the file.
+    pub fn parse(root: &Path, path: &Path) -> Result<Self, ParseError> {
+        let relative = path.strip_prefix(root)
+            .map_err(|_| ParseError::NotUnderRoot(path.to_path_buf()))?;
+
+        let mut components = relative.components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            });
+
+        let job = components.next()
+            .ok_or_else(|| ParseError::NoJob(path.to_path_buf()))?
+            .into_owned();
+
+        let mut segment = PathBuf::new();
+        let fab_name = loop {
+            match components.next() {
+                Some(name) if name.eq_ignore_ascii_case(FAB) => break name.into_owned(),
+                Some(name) => segment.push(name.as_ref()),
+                None => return Err(ParseError::NoFab(path.to_path_buf())),
+            }
+        };
+
+        let mut fab = PathBuf::new();
+        let mut artifact = None;
+        let mut artifact_name = None;
+        for name in components.by_ref() {
+            artifact = Artifact::from_folder(&name);
+            if artifact.is_some() {
+                artifact_name = Some(name.into_owned());
+                break;
+            }
+
+            fab.push(name.as_ref());
+        }
+
+        let file: PathBuf = components.map(|name| name.into_owned()).collect();
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            job,
+            segment: Some(segment).filter(|s| !s.as_os_str().is_empty()),
+            fab_name,
+            fab,
+            artifact,
+            artifact_name,
+            file: Some(file).filter(|f| !f.as_os_str().is_empty()),
+        })
+    }
+
+    /// Parse the job folder name as a job number
+    pub fn job_number(&self) -> Result<JobNumber, InvalidJobNumber> {
+        self.job.parse()
+    }
+
+    /// Path to the job folder
+    pub fn job_dir(&self) -> PathBuf {
+        self.root.join(&self.job)
+    }
+
+    /// Path to the `Fab` folder
+    pub fn fab_dir(&self) -> PathBuf {
+        let mut path = self.job_dir();
+        if let Some(segment) = &self.segment {
+            path.push(segment);
+        }
+        path.push(&self.fab_name);
+
+        path
+    }
+
+    /// Name of the artifact folder, as spelled on the share
+    pub fn artifact_folder(&self) -> Option<&str> {
+        let artifact = self.artifact?;
+
+        Some(self.artifact_name.as_deref().unwrap_or(artifact.folder()))
+    }
+
+    /// First folder under `Fab` (i.e. `Plates`), or the artifact folder
+    /// if it is directly under `Fab`
+    pub fn fab_folder(&self) -> Option<String> {
+        match self.fab.components().next() {
+            Some(c) => Some(c.as_os_str().to_string_lossy().into_owned()),
+            None => self.artifact_folder().map(String::from),
+        }
+    }
+
+    /// Build the path back up from its parts
+    ///
+    /// A file is only added if there is an artifact folder.
+    pub fn to_path(&self) -> PathBuf {
+        let mut path = self.fab_dir();
+        path.push(&self.fab);
+
+        if let Some(artifact) = self.artifact_folder() {
+            path.push(artifact);
+
+            if let Some(file) = &self.file {
+                path.push(file);
+            }
+        }
+
+        path
+    }
+}
+
+impl fmt::Display for JobPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_path().display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let root = Path::new("/jobs");
+        for path in [
+            "/jobs/1210123/Fab/Plates/DXF/1210123A-1.dxf",
+            "/jobs/1210123/Shipment 2/Fab/NC/1210123A-1.nc",
+            "/jobs/1210123/FAB/plates/dxf/1210123A-1.dxf",
+            "/jobs/1210123/Fab/Plates/Drawings/sub/1210123A-1.pdf",
+            "/jobs/1210123/Fab/Plates",
+        ] {
+            let parsed = JobPath::parse(root, Path::new(path)).unwrap();
+            assert_eq!(parsed.to_path(), Path::new(path), "{:?}", parsed);
+        }
+    }
+
+    #[test]
+    fn parts() {
+        let path = JobPath::parse(Path::new("/jobs"), Path::new("/jobs/1210123/Shipment 2/fab/Plates/Thick/dxf/part.dxf")).unwrap();
+
+        assert_eq!(path.job, "1210123");
+        assert_eq!(path.segment.as_deref(), Some(Path::new("Shipment 2")));
+        assert_eq!(path.fab_name, "fab");
+        assert_eq!(path.fab, Path::new("Plates/Thick"));
+        assert_eq!(path.artifact, Some(Artifact::Dxf));
+        assert_eq!(path.artifact_folder(), Some("dxf"));
+        assert_eq!(path.fab_folder().as_deref(), Some("Plates"));
+        assert_eq!(path.file.as_deref(), Some(Path::new("part.dxf")));
+    }
+
+    #[test]
+    fn not_a_job_path() {
+        let root = Path::new("/jobs");
+
+        assert_eq!(JobPath::parse(root, Path::new("/other/1210123")), Err(ParseError::NotUnderRoot(PathBuf::from("/other/1210123"))));
+        assert_eq!(JobPath::parse(root, root), Err(ParseError::NoJob(PathBuf::from("/jobs"))));
+        assert_eq!(JobPath::parse(root, Path::new("/jobs/1210123/DXF")), Err(ParseError::NoFab(PathBuf::from("/jobs/1210123/DXF"))));
+    }
+}
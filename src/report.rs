@@ -0,0 +1,169 @@
+//! Reclaimed space report
+//!
+//! Totals the files and bytes removed by a run, per job and per
+//! subfolder of the job's `Fab` folder, so that it can be shown how
+//! much space each run (and each job) gave back.
+//!
+//! For a file at `<root>\1210123\Fab\Plates\DXF\part.dxf`, the job is
+//! `1210123` and the `Fab` subfolder is `Plates`.
+//!
+//! Files that were left for a later run because they looked to be in
+//! use (see [`crate::in_use`]) are totalled separately.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::cleanup::Candidate;
+use crate::paths::JobPath;
+
+/// Job (or `Fab` subfolder) used for files that are not in one
+const NONE: &str = "-";
+
+/// Number of files and bytes
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Totals {
+    /// number of files
+    pub files: u64,
+
+    /// size of the files, in bytes
+    pub bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, files: u64, bytes: u64) {
+        self.files += files;
+        self.bytes += bytes;
+    }
+}
+
+/// Totals for a job
+#[derive(Debug, Default, Serialize)]
+pub struct JobTotals {
+    /// totals for the whole job
+    #[serde(flatten)]
+    pub totals: Totals,
+
+    /// totals for each `Fab` subfolder
+    pub folders: BTreeMap<String, Totals>,
+}
+
+/// Space reclaimed by a run
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// totals for the whole run
+    pub total: Totals,
+
+    /// totals for each job
+    pub jobs: BTreeMap<String, JobTotals>,
+
+    /// totals for files left for a later run
+    pub deferred: Totals,
+
+    #[serde(skip)]
+    roots: Vec<PathBuf>,
+}
+
+/// A row of the CSV report
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    job: &'a str,
+    folder: &'a str,
+    files: u64,
+    bytes: u64,
+}
+
+impl Report {
+    /// Create an empty report for files under the given roots
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self { roots, ..Default::default() }
+    }
+
+    /// Add a removed file (and its companion files)
+    pub fn add(&mut self, candidate: &Candidate) {
+        let files = 1 + candidate.companions.len() as u64;
+        let bytes = candidate.size();
+        let (job, folder) = self.job_and_folder(&candidate.file.path);
+
+        self.total.add(files, bytes);
+
+        let job = self.jobs.entry(job).or_default();
+        job.totals.add(files, bytes);
+        job.folders.entry(folder).or_default().add(files, bytes);
+    }
+
+    /// Add a file (and its companion files) left for a later run
+    pub fn defer(&mut self, candidate: &Candidate) {
+        self.deferred.add(1 + candidate.companions.len() as u64, candidate.size());
+    }
+
+    /// Job and `Fab` subfolder of a file
+    fn job_and_folder(&self, path: &Path) -> (String, String) {
+        let Some(path) = self.roots.iter().find_map(|root| JobPath::parse(root, path).ok()) else {
+            return (NONE.into(), NONE.into());
+        };
+
+        let folder = path.fab_folder().unwrap_or_else(|| NONE.into());
+
+        (path.job, folder)
+    }
+
+    /// Write the report as `<path>.csv` and `<path>.json`
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut wtr = csv::Writer::from_path(path.with_extension("csv"))?;
+        for (job, totals) in &self.jobs {
+            wtr.serialize(Row { job, folder: "", files: totals.totals.files, bytes: totals.totals.bytes })?;
+
+            for (folder, folder_totals) in &totals.folders {
+                wtr.serialize(Row { job, folder, files: folder_totals.files, bytes: folder_totals.bytes })?;
+            }
+        }
+        wtr.flush()?;
+
+        serde_json::to_writer_pretty(File::create(path.with_extension("json"))?, self)?;
+
+        Ok(())
+    }
+}
+
+/// Console table of the report
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<12} {:<24} {:>8} {:>12}", "Job", "Fab folder", "Files", "Size")?;
+        for (job, totals) in &self.jobs {
+            writeln!(f, "{:<12} {:<24} {:>8} {:>12}", job, "", totals.totals.files, bytes(totals.totals.bytes))?;
+
+            for (folder, folder_totals) in &totals.folders {
+                writeln!(f, "{:<12} {:<24} {:>8} {:>12}", "", folder, folder_totals.files, bytes(folder_totals.bytes))?;
+            }
+        }
+
+        write!(f, "{:<12} {:<24} {:>8} {:>12}", "Total", "", self.total.files, bytes(self.total.bytes))?;
+        if self.deferred.files > 0 {
+            write!(f, "\n{:<12} {:<24} {:>8} {:>12}", "Deferred", "(in use)", self.deferred.files, bytes(self.deferred.bytes))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Format a number of bytes for display (i.e. `4.2 MiB`)
+pub fn bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
@@ -0,0 +1,56 @@
+//! Rate limiting of filesystem operations
+//!
+//! The Jobs share is on a file server that is in use during shift
+//! hours, so walks and deletes can be held to a number of operations
+//! per second. A [`Throttle`] is shared by all the threads doing the
+//! work, so the limit is for the whole run rather than each thread.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Limits how often operations happen, across threads
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    /// Allow at most `per_second` operations a second (`0` for no limit)
+    pub fn new(per_second: u32) -> Self {
+        Self {
+            interval: (per_second > 0).then(|| Duration::from_secs(1) / per_second),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// No limit on operations
+    pub fn unlimited() -> Self {
+        Self::new(0)
+    }
+
+    /// Wait until another operation is allowed
+    pub fn wait(&self) {
+        let Some(interval) = self.interval else { return };
+
+        // take the next slot, then sleep until it comes up (without holding the lock)
+        let now = Instant::now();
+        let at = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let at = (*next).max(now);
+            *next = at + interval;
+            at
+        };
+
+        if at > now {
+            thread::sleep(at - now);
+        }
+    }
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}